@@ -1,47 +1,47 @@
+use codec::{Metadata, ToneBurst, SAMPLE_RATE};
 use hound::*;
-use std::io::Cursor;
 
-const SAMPLE_RATE: u32 = 44100;
-
-// Embed the start_signal.wav file directly into the binary
-const START_SIGNAL: &'static [u8] = include_bytes!("../start_signal.wav");
-
-pub fn write_audio_file(brightness_vec: Vec<u8>, filename: &str) {
-    // Use a Cursor to read the embedded start_signal.wav
-    let mut start_signal_reader =
-        WavReader::new(Cursor::new(START_SIGNAL)).expect("Failed to read start_signal.wav");
-
-    // Prepare the output file writer with the same specifications
+pub fn write_audio_file(samples: Vec<f32>, metadata: &Metadata, filename: &str) {
     let spec = WavSpec {
-        channels: 1,
+        channels: metadata.channels(),
         sample_rate: SAMPLE_RATE,
         bits_per_sample: 16,
         sample_format: SampleFormat::Int,
     };
     let mut writer = WavWriter::create(filename, spec).expect("Failed to create output file");
 
-    // Write the start signal samples to the output file
-    for sample in start_signal_reader.samples::<i16>() {
-        let sample_value = sample.expect("Failed to read sample from start_signal.wav");
-        writer
-            .write_sample(sample_value)
-            .expect("Failed to write start signal sample");
-    }
+    // Write the start signal samples to the output file, on every channel so it can be
+    // detected whichever one is listened to
+    write_tone_burst(&mut writer, &ToneBurst::start(), spec.channels);
 
-    // Now, process and write the brightness_vec samples as before
-    for val in brightness_vec {
-        let sample = scale(val) as f32;
+    // Now, write the encoded image samples
+    for sample in samples {
         writer
-            .write_sample((sample * i16::MAX as f32) as i16)
+            .write_sample(codec::sample_to_i16(sample))
             .expect("Failed to write brightness sample");
     }
 
+    // And the end signal, so a stream of several images can be told apart
+    write_tone_burst(&mut writer, &ToneBurst::end(), spec.channels);
+
     writer
         .finalize()
         .expect("Failed to finalize the output file");
+
+    // Record the dimensions and mode so the decoder doesn't have to guess them
+    codec::metadata::write(filename, metadata).expect("Failed to write metadata");
     println!("Done!");
 }
 
-fn scale(brightness: u8) -> f32 {
-    (brightness as f32 / 255.0) * 2.0 - 1.0
+fn write_tone_burst<W>(writer: &mut WavWriter<W>, tone_burst: &ToneBurst, channels: u16)
+where
+    W: std::io::Write + std::io::Seek,
+{
+    for sample in tone_burst.synthesize(SAMPLE_RATE) {
+        for _ in 0..channels {
+            writer
+                .write_sample(codec::sample_to_i16(sample))
+                .expect("Failed to write signal sample");
+        }
+    }
 }
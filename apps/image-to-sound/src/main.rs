@@ -1,4 +1,5 @@
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use codec::{Metadata, Mode, Modulation, ToneMap};
 use image::GenericImageView;
 mod audio_writer;
 
@@ -7,22 +8,93 @@ mod audio_writer;
 struct Cli {
     image_path: String,
     file_name: String,
+    #[arg(long, default_value_t = Mode::Luminance)]
+    mode: Mode,
+    /// Start every row with a sync marker, so the decoder can recover from dropped samples
+    #[arg(long)]
+    row_sync: bool,
+    /// fsk survives playing through speakers into a microphone, at a fraction of raw's speed
+    #[arg(long, default_value_t = Modulation::Raw)]
+    modulation: Modulation,
+    /// Above 1.0 brightens the mid tones, below darkens them
+    #[arg(long, default_value_t = 1.0, value_parser = positive)]
+    gamma: f32,
+    /// Scales how far values are from mid grey
+    #[arg(long, default_value_t = 1.0, value_parser = non_negative)]
+    contrast: f32,
+    /// Spread the values out so the whole range is used about equally
+    #[arg(long)]
+    equalise: bool,
+    /// Diffuse the rounding error to neighbouring pixels instead of banding
+    #[arg(long)]
+    dither: bool,
+}
+
+// The decoder can only undo a tone curve that rises, which these keep it to
+fn positive(s: &str) -> Result<f32, String> {
+    let value: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    if value > 0.0 {
+        Ok(value)
+    } else {
+        Err("has to be above 0".to_string())
+    }
+}
+
+fn non_negative(s: &str) -> Result<f32, String> {
+    let value: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    if value >= 0.0 {
+        Ok(value)
+    } else {
+        Err("can't be negative".to_string())
+    }
 }
 
 fn main() {
     println!("IMAGE TO SOUND CONVERTER");
     let args = Cli::parse();
+    if args.row_sync && args.modulation == Modulation::Fsk {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "fsk keeps its own timing, it doesn't take row sync markers",
+            )
+            .exit();
+    }
     println!("image_path: {:?}", args.image_path);
     println!("file_name: {:?}", args.file_name);
+    println!("mode: {}", args.mode);
+    println!("row_sync: {}", args.row_sync);
+    println!("modulation: {}", args.modulation);
+    let tone_map = ToneMap {
+        gamma: args.gamma,
+        contrast: args.contrast,
+        equalise: args.equalise,
+        dither: args.dither,
+    };
+    println!("tone_map: {:?}", tone_map);
     let img = image::open(args.image_path).unwrap();
     println!("dimensions {:?}", img.dimensions());
-    let mut brightnessVec: Vec<u8> = Vec::new();
 
-    for (_, _, pixel) in img.pixels() {
-        let rgba = pixel.0;
-        let brightness = (rgba[0] as u32 + rgba[1] as u32 + rgba[2] as u32) / 3;
-        brightnessVec.push(brightness as u8);
+    let channels = args.mode.channels() as usize;
+    let levels = match args.modulation {
+        Modulation::Raw => 256,
+        Modulation::Fsk => codec::fsk::LEVELS,
+    };
+    let values = codec::pixel_values(&img, args.mode);
+    let (values, tone_curve) = tone_map.apply(&values, img.width(), channels, levels);
+    let mut samples = match args.modulation {
+        Modulation::Raw => codec::encode_values(&values),
+        Modulation::Fsk => codec::fsk::modulate(&values),
+    };
+    if args.row_sync {
+        samples = codec::sync::insert_markers(&samples, img.width(), channels);
     }
+    let metadata = Metadata {
+        row_sync: args.row_sync,
+        modulation: args.modulation,
+        tone_curve: (!tone_map.is_identity()).then_some(tone_curve),
+        ..Metadata::new(img.width(), img.height(), args.mode)
+    };
 
-    audio_writer::write_audio_file(brightnessVec, &args.file_name);
+    audio_writer::write_audio_file(samples, &metadata, &args.file_name);
 }
@@ -0,0 +1,73 @@
+// Writes every completed image to a PNG named after when it completed, e.g.
+// 2026-10-18_21-04-33_7.png for the seventh image, to keep a record of each performance
+use super::decode;
+use super::fft::TIMESTAMP_FORMAT;
+use super::recorder::CompletedImage;
+use chrono::prelude::*;
+use codec::Mode;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+pub struct Archive {
+    dir: PathBuf,
+    // Decoding and compressing an image takes longer than a frame, so a thread of its own does it
+    writer: Sender<(PathBuf, Vec<f32>)>,
+    // Number and file of the image saved last, an image straightened after it completed
+    // overwrites it
+    last: Option<(usize, PathBuf)>,
+}
+
+// Makes sure there is somewhere to save to, before anything is captured
+pub fn create_dir(dir: &str) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("failed to create save directory {}: {}", dir, e))
+}
+
+impl Archive {
+    // `dir` has to exist already, see `create_dir`
+    pub fn new(dir: &str, mode: Mode, channels: usize, width: u32, height: u32) -> Self {
+        println!("saving images to {}", dir);
+        let (writer, images) = mpsc::channel::<(PathBuf, Vec<f32>)>();
+        // Ends once the archive is dropped and everything sent to it is written
+        thread::spawn(move || {
+            for (path, samples) in images {
+                let img = decode::decode_image(&samples, mode, channels as u32, width, height);
+                match img.save(&path) {
+                    Ok(()) => println!("saved {}", path.display()),
+                    Err(e) => println!("Failed to save {}: {}", path.display(), e),
+                }
+            }
+        });
+        Archive {
+            dir: PathBuf::from(dir),
+            writer,
+            last: None,
+        }
+    }
+
+    pub fn save(&mut self, completed: &CompletedImage) {
+        let path = match &self.last {
+            Some((number, path)) if *number == completed.number => path.clone(),
+            _ => self.dir.join(file_name(Utc::now(), completed.number)),
+        };
+        if self
+            .writer
+            .send((path.clone(), completed.samples.clone()))
+            .is_err()
+        {
+            println!("Failed to save {}, the writer is gone", path.display());
+        }
+        self.last = Some((completed.number, path));
+    }
+}
+
+// The log timestamp, with the characters file systems don't allow in names swapped out
+fn file_name(time: DateTime<Utc>, number: usize) -> String {
+    let timestamp = time
+        .format(TIMESTAMP_FORMAT)
+        .to_string()
+        .replace(['/', ':'], "-")
+        .replace(' ', "_");
+    format!("{}_{}.png", timestamp, number)
+}
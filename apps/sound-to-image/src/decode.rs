@@ -0,0 +1,184 @@
+use clap::Args;
+use codec::{Mode, Modulation, ToneBurst};
+use hound::{SampleFormat, WavReader, WavSpec};
+use image::{imageops, DynamicImage};
+
+#[derive(Args, Debug)]
+pub struct DecodeArgs {
+    input: String,
+    output: String,
+    /// Overrides the width recorded in the file
+    #[arg(long)]
+    width: Option<u32>,
+    /// Overrides the height recorded in the file
+    #[arg(long)]
+    height: Option<u32>,
+}
+
+pub fn run(args: DecodeArgs) {
+    println!("input: {:?}", args.input);
+    println!("output: {:?}", args.output);
+    let metadata = codec::metadata::read(&args.input).expect("Failed to read metadata");
+    match metadata {
+        Some(metadata) => println!("metadata: {:?}", metadata),
+        None => println!("no metadata, falling back to defaults"),
+    }
+    let width = args
+        .width
+        .or(metadata.map(|metadata| metadata.width))
+        .unwrap_or(codec::DEFAULT_WIDTH);
+    let height = args
+        .height
+        .or(metadata.map(|metadata| metadata.height))
+        .unwrap_or(codec::DEFAULT_HEIGHT);
+
+    let (spec, samples) = read_wav(&args.input);
+    let channels = spec.channels;
+    // Anything that isn't rgb is read as one luminance plane per channel
+    let mode = metadata
+        .map(|metadata| metadata.mode)
+        .or(Mode::from_channels(channels))
+        .unwrap_or(Mode::Luminance);
+
+    // Skip the start signal image-to-sound puts in front of the image
+    let start_signal_len = ToneBurst::start().len(spec.sample_rate) * channels as usize;
+    let mut image_samples = samples.get(start_signal_len..).unwrap_or(&[]).to_vec();
+    if metadata.is_some_and(|metadata| metadata.row_sync) {
+        image_samples = codec::sync::align_rows(&image_samples, width, channels as usize);
+    }
+    if metadata.is_some_and(|metadata| metadata.modulation == Modulation::Fsk) {
+        let failed_blocks;
+        (image_samples, failed_blocks) =
+            codec::fsk::demodulate(&image_samples, width, height, mode);
+        if failed_blocks > 0 {
+            println!("{} blocks had too many errors to correct", failed_blocks);
+        }
+    }
+    if let Some(tone_curve) = metadata.and_then(|metadata| metadata.tone_curve) {
+        // Undo the tone mapping the encoder applied
+        let inverse = tone_curve.inverse();
+        for sample in image_samples.iter_mut() {
+            let value = inverse[codec::sample_to_brightness(*sample) as usize];
+            *sample = codec::brightness_to_sample(value);
+        }
+    }
+    println!(
+        "decoding {} samples as {}x{} {}",
+        image_samples.len(),
+        width,
+        height,
+        mode
+    );
+
+    let img = decode_image(&image_samples, mode, channels as u32, width, height);
+    img.save(&args.output)
+        .expect("Failed to write output image");
+    println!("Done!");
+}
+
+// Returns the interleaved samples of a WAV file as f32, whichever format they are stored in
+pub fn read_wav(path: &str) -> (WavSpec, Vec<f32>) {
+    let mut reader = WavReader::open(path).expect("Failed to open input file");
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        // Scaled to the largest positive value of the width, so 16 bit files read back exactly
+        // like `codec::sample_from_i16` and the lowest value of any width lands on -1.0
+        SampleFormat::Int => {
+            let full_scale = ((1i64 << (spec.bits_per_sample - 1)) - 1) as f32;
+            reader
+                .samples::<i32>()
+                .map(|sample| {
+                    let sample = sample.expect("Failed to read sample") as f32 / full_scale;
+                    sample.max(-1.0)
+                })
+                .collect()
+        }
+        SampleFormat::Float => reader
+            .samples::<f32>()
+            .map(|sample| sample.expect("Failed to read sample"))
+            .collect(),
+    };
+    (spec, samples)
+}
+
+// Turns interleaved samples into the image the live view shows for them
+pub fn decode_image(
+    samples: &[f32],
+    mode: Mode,
+    channels: u32,
+    width: u32,
+    height: u32,
+) -> DynamicImage {
+    match mode {
+        Mode::Rgb => {
+            // Channels past the colours are left out, frames still take up all of them
+            let colours: Vec<f32> = samples
+                .chunks(channels as usize)
+                .flat_map(|frame| frame.iter().take(3))
+                .copied()
+                .collect();
+            codec::decode(&colours, width, height, mode)
+        }
+        Mode::Luminance => decode_planes(samples, channels, width, height),
+    }
+}
+
+// Lays the planes out side by side, the same way the live view does
+fn decode_planes(samples: &[f32], channels: u32, width: u32, height: u32) -> DynamicImage {
+    let mut planes = DynamicImage::new_luma8(width * channels, height);
+    for plane in 0..channels {
+        let plane_samples: Vec<f32> = samples
+            .iter()
+            .skip(plane as usize)
+            .step_by(channels as usize)
+            .copied()
+            .collect();
+        let img = codec::decode(&plane_samples, width, height, Mode::Luminance);
+        imageops::replace(&mut planes, &img, (plane * width) as i64, 0);
+    }
+    planes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hound::WavWriter;
+
+    fn read_back(bits_per_sample: u16, values: &[i32]) -> Vec<f32> {
+        let path = std::env::temp_dir().join(format!("read_wav_{}.wav", bits_per_sample));
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: codec::SAMPLE_RATE,
+            bits_per_sample,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(&path, spec).unwrap();
+        for value in values {
+            writer.write_sample(*value).unwrap();
+        }
+        writer.finalize().unwrap();
+        let (_, samples) = read_wav(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        samples
+    }
+
+    #[test]
+    fn reads_every_integer_width_as_full_scale() {
+        for bits_per_sample in [8, 16, 24, 32] {
+            let max = ((1i64 << (bits_per_sample - 1)) - 1) as i32;
+            let samples = read_back(bits_per_sample, &[max, 0, -max, -max - 1]);
+            assert_eq!(samples, [1.0, 0.0, -1.0, -1.0], "{} bit", bits_per_sample);
+        }
+    }
+
+    #[test]
+    fn leaves_extra_channels_out_of_rgb_images() {
+        let rgb = [-1.0, 0.0, 1.0, 1.0, -1.0, 0.0];
+        let with_fourth: Vec<f32> = rgb
+            .chunks(3)
+            .flat_map(|frame| frame.iter().copied().chain([0.5]))
+            .collect();
+        let decoded = decode_image(&with_fourth, Mode::Rgb, 4, 2, 1);
+        assert_eq!(decoded.as_bytes(), [0, 128, 255, 255, 0, 128]);
+    }
+}
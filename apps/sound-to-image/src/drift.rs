@@ -0,0 +1,161 @@
+// Keeps the captured audio at the rate the image was encoded at. The input device may run at a
+// different nominal rate (48 kHz interfaces playing back 44.1 kHz files) and its clock drifts
+// slightly against the one that played the file, which shows up as slanted rows.
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+// Measured drift beyond this is more likely a misdetected signal than a real clock difference
+const MAX_DRIFT: f64 = 0.02;
+
+// Frames on either side of an output frame that go into interpolating it. Linear interpolation
+// muffles the high tone of the start signal differently depending on where the output frame
+// falls, which throws the alignment off
+pub const HALF_TAPS: usize = 16;
+
+pub struct Resampler {
+    channels: usize,
+    // Input frames per output frame from the nominal sample rates
+    nominal_step: f64,
+    // Correction measured from previous images
+    drift: f64,
+    // The latest input frames, interleaved
+    history: VecDeque<f32>,
+    // Where the next output frame lies, in input frames from the oldest one in `history`
+    position: f64,
+    // Filter taps for the current output frame, kept to not allocate for every frame
+    weights: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(channels: usize) -> Self {
+        Resampler {
+            channels,
+            nominal_step: 1.0,
+            drift: 1.0,
+            history: VecDeque::new(),
+            position: (HALF_TAPS - 1) as f64,
+            weights: Vec::with_capacity(2 * HALF_TAPS),
+        }
+    }
+
+    pub fn set_rates(&mut self, input_rate: u32, output_rate: u32) {
+        self.nominal_step = input_rate as f64 / output_rate as f64;
+    }
+
+    pub fn drift(&self) -> f64 {
+        self.drift
+    }
+
+    // `measured` frames came in for an image that should have been `expected` frames long.
+    // Returns false if the difference is too large to be drift
+    pub fn correct(&mut self, measured: usize, expected: usize) -> bool {
+        let ratio = measured as f64 / expected as f64;
+        if (ratio - 1.0).abs() > MAX_DRIFT {
+            return false;
+        }
+        self.drift *= ratio;
+        true
+    }
+
+    // Appends the output frames that can be interpolated now this frame came in to `output`.
+    // They lag `HALF_TAPS` frames behind the input
+    pub fn process(&mut self, frame: &[f32], output: &mut Vec<f32>) {
+        let channels = self.channels;
+        self.history
+            .extend((0..channels).map(|channel| frame.get(channel).copied().unwrap_or(0.0)));
+        let frames = self.history.len() / channels;
+
+        let step = self.nominal_step * self.drift;
+        // Filter out what the output rate can't hold when going down in rate
+        let cutoff = (1.0 / step).min(1.0);
+        while self.position + HALF_TAPS as f64 <= (frames - 1) as f64 {
+            let center = self.position.floor() as usize;
+            // At matching rates the output frames land right on input frames
+            if step == 1.0 && self.position == center as f64 {
+                let frame = center * channels;
+                output.extend(self.history.range(frame..frame + channels));
+                self.position += step;
+                continue;
+            }
+            let first = center + 1 - HALF_TAPS;
+            let position = self.position;
+            self.weights.clear();
+            self.weights.extend((0..2 * HALF_TAPS).map(|tap| {
+                let distance = (first + tap) as f64 - position;
+                (windowed_sinc(distance, cutoff) * cutoff) as f32
+            }));
+            for channel in 0..channels {
+                let sample = self
+                    .weights
+                    .iter()
+                    .enumerate()
+                    .map(|(tap, weight)| self.history[(first + tap) * channels + channel] * weight)
+                    .sum();
+                output.push(sample);
+            }
+            self.position += step;
+        }
+
+        // Forget frames no output frame reaches back to anymore
+        let unused = (self.position.floor() as usize + 1).saturating_sub(HALF_TAPS);
+        let unused = unused.min(frames);
+        self.history.drain(..unused * channels);
+        self.position -= unused as f64;
+    }
+}
+
+// Hann windowed sinc, `distance` in input frames and `cutoff` relative to the input's Nyquist
+// frequency
+fn windowed_sinc(distance: f64, cutoff: f64) -> f64 {
+    let width = HALF_TAPS as f64;
+    if distance.abs() >= width {
+        return 0.0;
+    }
+    let window = 0.5 + 0.5 * (PI * distance / width).cos();
+    let x = PI * distance * cutoff;
+    let sinc = if x.abs() < 1e-9 { 1.0 } else { x.sin() / x };
+    sinc * window
+}
+
+// Stretches `captured` so that `measured` frames of it fill `expected` frames
+pub fn stretch(captured: &[f32], channels: usize, measured: usize, expected: usize) -> Vec<f32> {
+    let scale = measured as f64 / expected as f64;
+    let captured_frames = captured.len() / channels;
+    let mut stretched = Vec::with_capacity(expected * channels);
+    for i in 0..expected {
+        let position = i as f64 * scale;
+        let index = position.floor() as usize;
+        if index >= captured_frames {
+            break;
+        }
+        // The last frame has nothing after it to interpolate towards
+        let next = (index + 1).min(captured_frames - 1);
+        let t = (position - index as f64) as f32;
+        for channel in 0..channels {
+            let a = captured[index * channels + channel];
+            let b = captured[next * channels + channel];
+            stretched.push(a + (b - a) * t);
+        }
+    }
+    stretched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_frames_through_at_matching_rates() {
+        let mut resampler = Resampler::new(2);
+        resampler.set_rates(44100, 44100);
+        let input: Vec<f32> = (0..200).map(|i| (i as f32 * 0.1).sin()).collect();
+        let mut output = Vec::new();
+        for frame in input.chunks(2) {
+            resampler.process(frame, &mut output);
+        }
+        // The first output frame is the one with a full set of taps before it, and the last
+        // one the one with a full set after it
+        let frames = input.len() / 2;
+        assert_eq!(output, input[(HALF_TAPS - 1) * 2..(frames - HALF_TAPS) * 2]);
+    }
+}
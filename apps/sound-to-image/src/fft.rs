@@ -1,56 +1,463 @@
-use super::Model;
+use super::recorder::Capture;
 use chrono::prelude::*;
+use codec::ToneBurst;
 use ringbuf::Rb;
-use rustfft::{num_complex::Complex, FftPlanner};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::ops::Range;
+use std::sync::Arc;
 
-fn detect_start_signal(samples: Vec<f32>) -> bool {
-    if samples.is_empty() {
-        println!("No samples to analyze");
-        return false;
+// How to listen for the start and end signals. Loaded from a TOML file with `--detector-config`
+// or from the `[detector]` table of a profile, anything left out falls back to the defaults
+// below. The tones themselves come from the `[start_signal]` and `[end_signal]` tables, which
+// default to exactly what image-to-sound writes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DetectorConfig {
+    pub sample_rate: f32,
+    // Samples analysed per frame
+    pub window_size: usize,
+    // Consecutive frames the tones have to be heard in before the signal counts
+    pub debounce_frames: usize,
+    // Width of the band around each tone whose energy is measured, in Hz
+    pub bandwidth: f32,
+    // How far every band has to stand out above the noise floor, in dB
+    pub threshold_db: f32,
+    // How far every band may fall below the loudest bin, in dB, so artefacts of other loud
+    // tones don't count
+    pub peak_range_db: f32,
+    #[serde(deserialize_with = "ToneBurst::deserialize_start")]
+    pub start_signal: ToneBurst,
+    #[serde(deserialize_with = "ToneBurst::deserialize_end")]
+    pub end_signal: ToneBurst,
+}
+
+impl Default for DetectorConfig {
+    fn default() -> Self {
+        DetectorConfig {
+            sample_rate: codec::SAMPLE_RATE as f32,
+            window_size: 512,
+            debounce_frames: 2,
+            bandwidth: 200.0,
+            threshold_db: 20.0,
+            peak_range_db: 20.0,
+            start_signal: ToneBurst::start(),
+            end_signal: ToneBurst::end(),
+        }
     }
-    let sample_len = samples.len();
-    // Create an FFT planner
-    let mut planner = FftPlanner::<f32>::new();
-    let fft = planner.plan_fft_forward(sample_len);
+}
 
-    // Convert samples to complex numbers (real part is the sample, imaginary part is 0)
-    let mut buffer: Vec<_> = samples
-        .into_iter()
-        .map(|s| Complex { re: s, im: 0.0 })
-        .collect();
+impl DetectorConfig {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read detector config {}: {}", path, e))?;
+        toml::from_str(&text)
+            .map_err(|e| format!("failed to parse detector config {}: {}", path, e))
+    }
+
+    // Why the detectors can't work with this, if they can't
+    pub fn validate(&self) -> Result<(), String> {
+        if self.window_size < 2 {
+            return Err("the detector window_size has to be at least 2".to_string());
+        }
+        if self.sample_rate <= 0.0 {
+            return Err("the detector sample_rate has to be above 0".to_string());
+        }
+        for (name, tone_burst) in [("start", &self.start_signal), ("end", &self.end_signal)] {
+            if tone_burst.frequencies.is_empty() {
+                return Err(format!("the {} signal needs at least one frequency", name));
+            }
+            // The noise floor comes from the bins outside the bands
+            let bins = self.window_size / 2;
+            let covered = (0..bins)
+                .filter(|bin| {
+                    let frequencies = tone_burst.frequencies.iter();
+                    frequencies
+                        .map(|f| band(self, *f))
+                        .any(|band| band.contains(bin))
+                })
+                .count();
+            if covered == bins {
+                return Err(format!(
+                    "the bands around the {} signal's frequencies leave no bins to measure the \
+                     noise floor in, narrow the bandwidth",
+                    name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Detector {
+    config: DetectorConfig,
+    frequencies: Vec<f32>,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    hits: usize,
+}
+
+impl Detector {
+    pub fn new(config: &DetectorConfig, tone_burst: &ToneBurst) -> Self {
+        let size = config.window_size;
+        let fft = FftPlanner::new().plan_fft_forward(size);
+        // Hann window, so energy from the image doesn't leak into the target bands
+        let window = (0..size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (size - 1) as f32).cos())
+            .collect();
+        Detector {
+            config: config.clone(),
+            frequencies: tone_burst.frequencies.clone(),
+            fft,
+            window,
+            hits: 0,
+        }
+    }
+
+    pub fn is_detected(&self) -> bool {
+        self.hits >= self.config.debounce_frames
+    }
+
+    // Feeds the latest samples in and returns whether the signal is currently heard
+    pub fn process(&mut self, samples: &[f32]) -> bool {
+        match self.tones_present(samples) {
+            Some(true) => self.hits += 1,
+            Some(false) => self.hits = 0,
+            // Not enough new audio yet, keep the previous state
+            None => {}
+        }
+        self.is_detected()
+    }
+
+    fn tones_present(&self, samples: &[f32]) -> Option<bool> {
+        let size = self.config.window_size;
+        if samples.len() < size {
+            return None;
+        }
+
+        // Convert samples to complex numbers (real part is the windowed sample, imaginary part is 0)
+        let mut buffer: Vec<_> = samples[samples.len() - size..]
+            .iter()
+            .zip(&self.window)
+            .map(|(s, w)| Complex { re: s * w, im: 0.0 })
+            .collect();
+
+        // Perform the FFT
+        self.fft.process(&mut buffer);
+
+        // Only the first half of the spectrum carries information for real input
+        let power: Vec<f32> = buffer[..size / 2].iter().map(|c| c.norm_sqr()).collect();
+        let bands: Vec<Range<usize>> = self
+            .frequencies
+            .iter()
+            .map(|f| band(&self.config, *f))
+            .collect();
+
+        // The noise floor is the median power of everything outside the target bands
+        let mut noise: Vec<f32> = power
+            .iter()
+            .enumerate()
+            .filter(|(bin, _)| !bands.iter().any(|band| band.contains(bin)))
+            .map(|(_, p)| *p)
+            .collect();
+        if noise.is_empty() {
+            return Some(false);
+        }
+        noise.sort_by(|a, b| a.total_cmp(b));
+        let noise_floor = noise[noise.len() / 2].max(f32::MIN_POSITIVE);
+        let peak = power.iter().copied().fold(f32::MIN_POSITIVE, f32::max);
+
+        let present = bands.iter().all(|band| {
+            let energy = power[band.clone()].iter().sum::<f32>() / band.len() as f32;
+            10.0 * (energy / noise_floor).log10() >= self.config.threshold_db
+                && 10.0 * (peak / energy).log10() <= self.config.peak_range_db
+        });
+        Some(present)
+    }
+}
+
+// FFT bins within half the bandwidth of `frequency`
+fn band(config: &DetectorConfig, frequency: f32) -> Range<usize> {
+    let size = config.window_size;
+    let bin_width = config.sample_rate / size as f32;
+    let last_bin = size / 2 - 1;
+    let half_bandwidth = config.bandwidth / 2.0;
+    let low = ((frequency - half_bandwidth) / bin_width).floor().max(0.0) as usize;
+    let high = ((frequency + half_bandwidth) / bin_width).ceil() as usize;
+    let low = low.min(last_bin);
+    low..high.clamp(low, last_bin) + 1
+}
+
+// Frames of audio the recorder keeps around for the detectors. Has to cover a signal's sweep plus
+// however long it takes for the detector to notice its tones stopped
+pub const MONITOR_LEN: usize = 32768;
 
-    // Perform the FFT
-    fft.process(&mut buffer);
+// Matches with a lower normalised correlation are not trusted
+const MIN_ALIGNMENT_SCORE: f32 = 0.5;
 
-    let target_frequencies = [(200.0, 10.0), (16000.0, 15.0)];
-    let sample_rate = 44100; // Your audio sample rate
+pub struct Detectors {
+    pub start: Detector,
+    pub end: Detector,
+    // The sweeps the signals close with, to find the exact sample they end on
+    start_template: Vec<f32>,
+    end_template: Vec<f32>,
+    end_signal_len: usize,
+    // Frames heard since a signal's tones stopped, while waiting for its sweep to come in
+    start_pending: Option<usize>,
+    end_pending: Option<usize>,
+}
 
-    for &(freq, thresh) in &target_frequencies {
-        let bin = (freq / sample_rate as f32) * sample_len as f32;
-        let amplitude = buffer[bin as usize].norm(); // Simplified, consider using a range around `bin`
-        if amplitude < thresh {
-            return false; // If any amplitude is below its threshold, return false
+impl Detectors {
+    pub fn new(config: &DetectorConfig) -> Self {
+        let sample_rate = config.sample_rate as u32;
+        let sweep = |tone_burst: &ToneBurst| {
+            let signal = tone_burst.synthesize(sample_rate);
+            let sweep_start = signal.len() - tone_burst.sweep_len(sample_rate);
+            signal[sweep_start..].to_vec()
+        };
+        Detectors {
+            start: Detector::new(config, &config.start_signal),
+            end: Detector::new(config, &config.end_signal),
+            start_template: sweep(&config.start_signal),
+            end_template: sweep(&config.end_signal),
+            end_signal_len: config.end_signal.len(sample_rate),
+            start_pending: None,
+            end_pending: None,
         }
     }
+}
 
+// Adds the `fresh` frames to a pending signal and returns whether its sweep is all in now
+fn sweep_heard(pending: &mut Option<usize>, fresh: usize, sweep_len: usize) -> bool {
+    let Some(heard) = pending.as_mut() else {
+        return false;
+    };
+    *heard += fresh;
+    if *heard < sweep_len {
+        return false;
+    }
+    *pending = None;
     true
 }
 
-pub fn update(model: &mut Model) {
-    let mut rb = model.rb.lock().unwrap();
-    let samples: Vec<f32> = rb.iter().copied().collect();
-    let sample_count = 512;
-    let samples_to_consider = if samples.len() > sample_count {
-        &samples[(samples.len() - sample_count)..]
-    } else {
-        &samples[..]
+// Windows quieter than this, relative to the template, are never a match
+const SILENCE: f64 = 1e-6;
+
+// Returns the index in `samples` right after the best match of `template`, and how well it
+// matched (normalised cross-correlation, 1.0 being a perfect match)
+pub fn find_signal_end(samples: &[f32], template: &[f32]) -> Option<(usize, f32)> {
+    let len = template.len();
+    if len == 0 || samples.len() < len {
+        return None;
+    }
+
+    // Correlate in the frequency domain, the direct way is too slow to do on the render thread
+    let size = (samples.len() + len).next_power_of_two();
+    let mut planner = FftPlanner::<f32>::new();
+    let forward = planner.plan_fft_forward(size);
+    let inverse = planner.plan_fft_inverse(size);
+    let to_complex = |values: &[f32]| {
+        let mut buffer: Vec<Complex<f32>> =
+            values.iter().map(|v| Complex { re: *v, im: 0.0 }).collect();
+        buffer.resize(size, Complex { re: 0.0, im: 0.0 });
+        buffer
     };
-    if detect_start_signal(samples_to_consider.to_vec()) {
-        let now = Utc::now();
-        println!(
-            "\x1b[36m{}: \x1b[0mStart signal detected",
-            now.format("%Y/%m/%d %H:%M:%S")
+    let mut received = to_complex(samples);
+    let mut expected = to_complex(template);
+    forward.process(&mut received);
+    forward.process(&mut expected);
+    let mut correlation: Vec<_> = received
+        .iter()
+        .zip(&expected)
+        .map(|(r, e)| r * e.conj())
+        .collect();
+    inverse.process(&mut correlation);
+
+    // Running energy of the received audio under the template, for normalising
+    let mut energy = vec![0.0f64; samples.len() + 1];
+    for (i, sample) in samples.iter().enumerate() {
+        energy[i + 1] = energy[i] + (*sample as f64).powi(2);
+    }
+    let template_energy: f64 = template.iter().map(|t| (*t as f64).powi(2)).sum();
+
+    (0..=samples.len() - len)
+        .map(|offset| {
+            let dot = correlation[offset].re as f64 / size as f64;
+            let window_energy = energy[offset + len] - energy[offset];
+            // Digital silence would turn rounding errors into perfect matches
+            if window_energy < template_energy * SILENCE {
+                return (offset + len, 0.0);
+            }
+            let score = dot / (window_energy * template_energy).sqrt();
+            (offset + len, score as f32)
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+// Listens to what came into `capture` since the last call, and starts and ends its image on the
+// signals
+pub fn update(detectors: &mut Detectors, capture: &mut Capture) {
+    // Only listen again once new audio came in
+    let fresh = capture.fresh;
+    if fresh == 0 {
+        return;
+    }
+    capture.fresh = 0;
+    // The signals are written to every channel, so the first one is enough
+    let samples: Vec<f32> = capture
+        .monitor
+        .iter()
+        .step_by(capture.channels())
+        .copied()
+        .collect();
+
+    let was_started = detectors.start.is_detected();
+    if detectors.start.process(&samples) {
+        if !was_started {
+            log("Start signal detected");
+        }
+        // Keep restarting for as long as the signal lasts, so nothing before it ends up in the image
+        capture.start();
+        detectors.start_pending = None;
+    } else if was_started {
+        detectors.start_pending = Some(0);
+    } else if sweep_heard(
+        &mut detectors.start_pending,
+        fresh,
+        detectors.start_template.len(),
+    ) {
+        // The signal is over, line the image up with the exact sample it ended on
+        match find_signal_end(&samples, &detectors.start_template) {
+            Some((end, score)) if score >= MIN_ALIGNMENT_SCORE => {
+                let frames = samples.len() - end;
+                log(&format!(
+                    "Start signal ended {} samples ago (match {:.2})",
+                    frames, score
+                ));
+                capture.start_from_monitor(frames);
+            }
+            _ => log("Couldn't find the end of the start signal"),
+        }
+    }
+
+    let was_ended = detectors.end.is_detected();
+    if detectors.end.process(&samples) {
+        if !was_ended {
+            log("End signal detected");
+            capture.finish();
+        }
+        detectors.end_pending = None;
+    } else if was_ended {
+        detectors.end_pending = Some(0);
+    } else if sweep_heard(
+        &mut detectors.end_pending,
+        fresh,
+        detectors.end_template.len(),
+    ) {
+        // Knowing exactly where the end signal began tells how long the image really was
+        match find_signal_end(&samples, &detectors.end_template) {
+            Some((end, score)) if score >= MIN_ALIGNMENT_SCORE => {
+                capture.end_at(samples.len() - end + detectors.end_signal_len);
+            }
+            _ => log("Couldn't find the end of the end signal"),
+        }
+    }
+}
+
+// How log lines and the archive (see archive.rs) are timestamped
+pub const TIMESTAMP_FORMAT: &str = "%Y/%m/%d %H:%M:%S";
+
+fn log(message: &str) {
+    let now = Utc::now();
+    println!(
+        "\x1b[36m{}: \x1b[0m{}",
+        now.format(TIMESTAMP_FORMAT),
+        message
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pixel-like values with a little noise on top, from a xorshift generator
+    fn image_like(len: usize) -> Vec<f32> {
+        let mut state: u32 = 0x9e37_79b9;
+        (0..len)
+            .map(|i| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                let noise = (state as f32 / u32::MAX as f32 - 0.5) * 0.1;
+                ((i / 7 % 37) as f32 / 37.0 - 0.5) + noise
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_the_exact_end_of_the_start_signal() {
+        let sample_rate = codec::SAMPLE_RATE;
+        let detectors = Detectors::new(&DetectorConfig::default());
+        let burst = ToneBurst::start().synthesize(sample_rate);
+        for offset in [0, 1234, 5001] {
+            let mut samples = image_like(offset);
+            samples.extend(&burst);
+            samples.extend(image_like(3000));
+            let (end, score) = find_signal_end(&samples, &detectors.start_template).unwrap();
+            assert_eq!(end, offset + burst.len(), "offset {}", offset);
+            assert!(score > 0.9, "score {}", score);
+        }
+    }
+
+    #[test]
+    fn no_match_without_the_signal() {
+        let detectors = Detectors::new(&DetectorConfig::default());
+        let template = &detectors.start_template;
+        assert_eq!(
+            find_signal_end(&image_like(template.len() - 1), template),
+            None
+        );
+        assert_eq!(find_signal_end(&image_like(1000), &[]), None);
+
+        let (_, score) = find_signal_end(&image_like(20000), template).unwrap();
+        assert!(score < MIN_ALIGNMENT_SCORE, "score {}", score);
+        let (_, score) = find_signal_end(&vec![0.0; 20000], template).unwrap();
+        assert_eq!(score, 0.0);
+    }
+
+    #[test]
+    fn partial_signal_tables_keep_the_rest_of_their_burst() {
+        let config: DetectorConfig = toml::from_str(
+            "
+            [start_signal]
+            frequencies = [300.0, 15000.0]
+
+            [end_signal]
+            level = 0.5
+            sweep = { duration = 0.1 }
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            config.start_signal,
+            ToneBurst {
+                frequencies: vec![300.0, 15000.0],
+                ..ToneBurst::start()
+            }
+        );
+        let end = ToneBurst::end();
+        assert_eq!(
+            config.end_signal,
+            ToneBurst {
+                level: 0.5,
+                sweep: codec::signal::Sweep {
+                    duration: 0.1,
+                    ..end.sweep
+                },
+                ..end
+            }
         );
-        rb.clear();
+        assert_eq!(config.window_size, DetectorConfig::default().window_size);
     }
 }
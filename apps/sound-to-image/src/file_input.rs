@@ -0,0 +1,60 @@
+// Plays a WAV file into the capture instead of the microphone, at the pace it would have been
+// heard at or faster. Files written by image-to-sound then show up exactly as they would live,
+// which makes rehearsing the visual side repeatable.
+use super::decode;
+use super::source::AudioSource;
+
+pub struct WavSource {
+    path: String,
+    sample_rate: u32,
+    channels: usize,
+    samples: Vec<f32>,
+    position: usize,
+}
+
+impl WavSource {
+    pub fn open(path: &str) -> Self {
+        let (spec, samples) = decode::read_wav(path);
+        println!(
+            "{}: {} Hz, {} channels",
+            path, spec.sample_rate, spec.channels
+        );
+        WavSource {
+            path: path.to_string(),
+            sample_rate: spec.sample_rate,
+            channels: spec.channels as usize,
+            samples,
+            position: 0,
+        }
+    }
+}
+
+impl AudioSource for WavSource {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn read(&mut self, frames: usize, buffer: &mut Vec<f32>) -> usize {
+        let remaining = &self.samples[self.position..];
+        let len = remaining.len().min(frames.saturating_mul(self.channels));
+        buffer.extend_from_slice(&remaining[..len]);
+        self.position += len;
+        len / self.channels
+    }
+
+    fn is_live(&self) -> bool {
+        false
+    }
+
+    fn is_finished(&self) -> bool {
+        self.position == self.samples.len()
+    }
+
+    fn name(&self) -> String {
+        self.path.clone()
+    }
+}
@@ -0,0 +1,88 @@
+// The last few completed images, so what has been transmitted so far can be looked at again or
+// all at once as a grid. The images themselves are layers of a texture array on the GPU (see
+// simple_shader), this only keeps track of which layer holds which. The newest image is in layer
+// `(count - 1) % layers`, the ones before it in the layers below, wrapping around.
+use nannou::wgpu;
+
+// Images a gallery can hold, as many layers as a texture array may have under wgpu's default
+// limits
+pub fn max_layers() -> usize {
+    wgpu::Limits::default().max_texture_array_layers as usize
+}
+
+// Widest and tallest a layer may be. Layers are as wide as an image's row with all its channels
+pub fn max_layer_size() -> u32 {
+    wgpu::Limits::default().max_texture_dimension_2d
+}
+
+pub struct Gallery {
+    layers: usize,
+    // Images added so far
+    count: usize,
+    // Number the capture gave the newest image, see `CompletedImage::number`
+    newest: Option<usize>,
+    // How many images back from the newest the one on screen is, None for the live image
+    pub shown: Option<usize>,
+    // Tile all images instead of showing one
+    pub grid: bool,
+}
+
+impl Gallery {
+    pub fn new(layers: usize) -> Self {
+        Gallery {
+            layers: layers.max(1),
+            count: 0,
+            newest: None,
+            shown: None,
+            grid: false,
+        }
+    }
+
+    pub fn layers(&self) -> usize {
+        self.layers
+    }
+
+    pub fn len(&self) -> usize {
+        self.count.min(self.layers)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    // Returns the layer the completed image `number` goes in. An image that was straightened
+    // after it completed replaces the version added before
+    pub fn add(&mut self, number: usize) -> usize {
+        if self.newest != Some(number) {
+            self.newest = Some(number);
+            self.count += 1;
+            // Stay on the image being looked at, unless it was just overwritten
+            if let Some(back) = self.shown {
+                self.shown = (back + 1 < self.layers).then_some(back + 1);
+            }
+        }
+        self.layer(0)
+    }
+
+    // Layer of the image `back` images before the newest
+    pub fn layer(&self, back: usize) -> usize {
+        (self.count - 1 - back) % self.layers
+    }
+
+    // Goes from the live image to the newest one and on to older ones
+    pub fn older(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+        let back = self.shown.map_or(0, |back| back + 1);
+        self.shown = Some(back.min(self.len() - 1));
+    }
+
+    // Goes back towards the live image
+    pub fn newer(&mut self) {
+        self.shown = match self.shown {
+            Some(0) | None => None,
+            Some(back) => Some(back - 1),
+        };
+    }
+}
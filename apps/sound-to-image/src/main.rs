@@ -1,6 +1,16 @@
+use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand};
+use codec::{Mode, Modulation};
 use nannou::prelude::*;
 use wgpu::*;
+mod archive;
+mod decode;
+mod drift;
 mod fft;
+mod file_input;
+mod gallery;
+mod network;
+mod source;
+mod synthetic;
 
 mod simple_shader;
 use simple_shader::*;
@@ -8,22 +18,27 @@ use simple_shader::*;
 mod helpers;
 use helpers::*;
 
+mod profile;
+use profile::{Profile, ProfileFile};
+
 mod recorder;
-use recorder::{AppAudioBuffer, RecorderInStream};
+use archive::Archive;
+use gallery::Gallery;
+use recorder::{Capture, Decoder};
+use source::{AudioSource, Feeder};
 
 mod ui;
 use ui::AppUi;
 
-pub const WIDTH: usize = 500;
-pub const HEIGHT: usize = 500;
-
 struct Model {
-    #[allow(unused)]
-    in_stream: RecorderInStream,
+    feeder: Feeder,
 
-    rb: AppAudioBuffer,
+    rb: Capture,
+    detectors: fft::Detectors,
     shader_settings: SetupRenderPipelineOutput,
     ui: AppUi,
+    gallery: Gallery,
+    archive: Option<Archive>,
 }
 
 #[repr(C)]
@@ -32,21 +47,219 @@ pub struct Uniforms {
     time: f32,
     window_width: f32,
     window_height: f32, // New fields for window dimensions
+    mode: u32,          // 0: luminance, 1: rgb
+    channels: u32,      // interleaved channels per frame in the audio storage buffer
+    image_width: u32,
+    image_height: u32,
+    // From the settings panel, see colorAt in fs.wgsl
+    contrast: f32,
+    gain: f32,
+    offset: f32,
+    colour_map: u32, // 0: grey, 1: inverted, 2: heat, 3: viridis
+    // Samples of the current image in the audio storage buffer, anything after them is left
+    // over from before
+    write_cursor: u32,
+    reveal: u32,    // 1: draw only what is up to write_cursor
+    scan_head: u32, // 1: mark write_cursor while revealing
+    // How much of the last completed image is shown over the current one, 0 to 1
+    hold: f32,
+    held_layer: u32, // layer of the gallery texture holding it
+    view: u32, // 0: the live image, 1: the gallery image in shown_layer, 2: the gallery as a grid
+    shown_layer: u32,
+    gallery_len: u32,    // images in the gallery
+    gallery_newest: u32, // layer of the newest one, the ones before it are in the layers below
+    gallery_layers: u32,
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    #[command(flatten)]
+    live: LiveArgs,
+}
+
+// Anything given here overrides the preset, which overrides the defaults
+#[derive(Args, Debug)]
+struct LiveArgs {
+    /// TOML or JSON file holding named presets of all settings, created when saving from the UI
+    #[arg(long, default_value = "profiles.toml")]
+    profile: String,
+    /// Preset in the profile file to start with, defaults to the last one saved
+    #[arg(long)]
+    preset: Option<String>,
+    #[arg(long)]
+    mode: Option<Mode>,
+    /// Input channels to capture, defaults to the channels the mode needs. In luminance mode
+    /// every channel is shown as its own image plane
+    #[arg(long)]
+    channels: Option<usize>,
+    #[arg(long)]
+    width: Option<u32>,
+    #[arg(long)]
+    height: Option<u32>,
+    /// TOML file describing the start signal tones and detection thresholds
+    #[arg(long)]
+    detector_config: Option<String>,
+    /// Expect a sync marker in front of every row, as written by image-to-sound --row-sync
+    #[arg(long)]
+    row_sync: bool,
+    /// How the images were turned into sound by image-to-sound
+    #[arg(long)]
+    modulation: Option<Modulation>,
+    /// Name or index of the input device, see the devices command
+    #[arg(long)]
+    device: Option<String>,
+    /// Rate to open the input device at
+    #[arg(long)]
+    sample_rate: Option<u32>,
+    /// Frames per buffer the input device hands over
+    #[arg(long)]
+    buffer_size: Option<usize>,
+    /// Play a WAV file, such as one written by image-to-sound, instead of listening to the input
+    #[arg(long)]
+    file: Option<String>,
+    /// Show a generated test pattern instead of listening to the input
+    #[arg(long)]
+    synthetic: bool,
+    /// Take audio streamed over TCP as little-endian f32 samples, e.g. --listen 0.0.0.0:7878
+    #[arg(long)]
+    listen: Option<String>,
+    /// Completed images to keep for browsing
+    #[arg(long)]
+    gallery: Option<usize>,
+    /// Save every completed image to a timestamped PNG in this directory
+    #[arg(long)]
+    save_dir: Option<String>,
+    /// How much faster than real time to play --file or --synthetic
+    #[arg(long, default_value_t = 1.0)]
+    speed: f32,
+}
+
+impl LiveArgs {
+    // Returns the settings to start with, the profile file and the name of the preset used.
+    // Exits with a usage error if they don't go together
+    fn resolve(&self) -> (Profile, ProfileFile, String) {
+        let profiles = ProfileFile::load(&self.profile)
+            .unwrap_or_else(|message| Cli::command().error(ErrorKind::Io, message).exit());
+        let selected = profiles
+            .select(self.preset.as_deref())
+            .unwrap_or_else(|message| {
+                Cli::command()
+                    .error(ErrorKind::InvalidValue, message)
+                    .exit()
+            });
+        let (preset_name, mut profile) = match selected {
+            Some((name, profile)) => (name.clone(), profile.clone()),
+            None => (String::new(), Profile::default()),
+        };
+        profile.mode = self.mode.unwrap_or(profile.mode);
+        profile.channels = self.channels.or(profile.channels);
+        profile.width = self.width.unwrap_or(profile.width);
+        profile.height = self.height.unwrap_or(profile.height);
+        profile.row_sync |= self.row_sync;
+        profile.modulation = self.modulation.unwrap_or(profile.modulation);
+        profile.gallery = self.gallery.unwrap_or(profile.gallery);
+        profile.input.device = self.device.clone().or(profile.input.device);
+        profile.input.sample_rate = self.sample_rate.or(profile.input.sample_rate);
+        profile.input.buffer_size = self.buffer_size.or(profile.input.buffer_size);
+        if let Some(path) = &self.detector_config {
+            profile.detector = fft::DetectorConfig::load(path)
+                .unwrap_or_else(|message| Cli::command().error(ErrorKind::Io, message).exit());
+        }
+        if let Err(message) = profile.validate() {
+            Cli::command()
+                .error(ErrorKind::ArgumentConflict, message)
+                .exit();
+        }
+        if let Some(dir) = &self.save_dir {
+            archive::create_dir(dir)
+                .unwrap_or_else(|message| Cli::command().error(ErrorKind::Io, message).exit());
+        }
+        (profile, profiles, preset_name)
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Decode an encoded WAV file to an image without opening a window
+    Decode(decode::DecodeArgs),
+    /// List the audio input devices
+    Devices,
 }
 
 fn main() {
-    nannou::app(model).update(update).run();
+    let args = Cli::parse();
+    match args.command {
+        Some(Commands::Decode(decode_args)) => decode::run(decode_args),
+        Some(Commands::Devices) => {
+            for (index, name) in recorder::input_device_names().iter().enumerate() {
+                println!("{}: {}", index, name);
+            }
+        }
+        None => {
+            // Catch bad options before the window opens
+            args.live.resolve();
+            nannou::app(model).update(update).run()
+        }
+    }
 }
 
 fn model(app: &App) -> Model {
     app.new_window()
         .fullscreen()
         .view(view)
+        .key_pressed(key_pressed)
         .raw_event(raw_window_event)
         .build()
         .unwrap();
 
-    let (rb, in_stream) = recorder::create();
+    // nannou's model function can't capture anything, so read the options again here
+    let args = Cli::parse().live;
+    let (profile, profiles, preset_name) = args.resolve();
+    if !preset_name.is_empty() {
+        println!("preset: {:?} from {}", preset_name, profiles.path);
+    }
+    let channels = profile.channels();
+    let frames = (profile.width * profile.height) as usize;
+    let detector_config = &profile.detector;
+    let detectors = fft::Detectors::new(detector_config);
+    let decoder = match profile.modulation {
+        Modulation::Fsk => {
+            let demodulator =
+                codec::fsk::Demodulator::new(profile.width, profile.height, profile.mode);
+            Some(Decoder::Fsk(demodulator))
+        }
+        Modulation::Raw => profile
+            .row_sync
+            .then(|| Decoder::RowSync(codec::sync::RowAligner::new(profile.width, channels))),
+    };
+    let rb = Capture::new(
+        channels,
+        frames,
+        fft::MONITOR_LEN,
+        detector_config.sample_rate as u32,
+        decoder,
+    );
+    let source: Box<dyn AudioSource> = if let Some(path) = &args.file {
+        Box::new(file_input::WavSource::open(path))
+    } else if args.synthetic {
+        Box::new(synthetic::Synthetic::new(&profile))
+    } else if let Some(address) = &args.listen {
+        let sample_rate = detector_config.sample_rate as u32;
+        Box::new(network::NetworkSource::listen(
+            address,
+            channels,
+            sample_rate,
+        ))
+    } else {
+        Box::new(recorder::open_input(channels, &profile.input).expect("Failed to open input"))
+    };
+    let feeder = Feeder::new(source, args.speed);
+    // Only the input device can be switched from the settings panel
+    let live_input = args.file.is_none() && !args.synthetic && args.listen.is_none();
 
     let window = app.main_window();
     let device = window.device();
@@ -56,7 +269,15 @@ fn model(app: &App) -> Model {
 
     let sample_count = window.msaa_samples();
 
-    let ui = ui::create_ui(&window);
+    let mode = profile.mode;
+    let (image_width, image_height) = (profile.width, profile.height);
+    let gallery = Gallery::new(profile.gallery);
+    let archive = args
+        .save_dir
+        .as_deref()
+        .map(|dir| Archive::new(dir, mode, channels, image_width, image_height));
+    let ui = ui::create_ui(&window, profile, profiles, preset_name, live_input);
+    let settings = &ui.settings;
     let scale_factor = app.main_window().scale_factor() as f32;
     let window_rect = app.main_window().rect();
     let uniforms = Uniforms {
@@ -64,6 +285,27 @@ fn model(app: &App) -> Model {
         // Adjust for DPI scaling
         window_width: window_rect.w() * scale_factor,
         window_height: window_rect.h() * scale_factor,
+        mode: match mode {
+            Mode::Luminance => 0,
+            Mode::Rgb => 1,
+        },
+        channels: channels as u32,
+        image_width,
+        image_height,
+        contrast: settings.contrast,
+        gain: settings.gain,
+        offset: settings.offset,
+        colour_map: settings.colour_map.index(),
+        write_cursor: 0,
+        reveal: settings.reveal as u32,
+        scan_head: settings.scan_head as u32,
+        hold: 0.0,
+        held_layer: 0,
+        view: 0,
+        shown_layer: 0,
+        gallery_len: 0,
+        gallery_newest: 0,
+        gallery_layers: gallery.layers() as u32,
     };
 
     let shader_settings = setup_render_pipeline(SetupRenderPipelineParams {
@@ -72,32 +314,68 @@ fn model(app: &App) -> Model {
         fs_desc,
         sample_count,
         uniforms: &uniforms,
+        audio_buffer_len: frames * channels,
+        gallery_layers: gallery.layers() as u32,
     });
 
     Model {
         rb,
-        in_stream,
+        detectors,
+        feeder,
         ui,
         shader_settings,
+        gallery,
+        archive,
     }
 }
 
 fn update(app: &App, model: &mut Model, _update: Update) {
-    fft::update(model);
+    model.feeder.feed(&mut model.rb);
+    fft::update(&mut model.detectors, &mut model.rb);
+    if let (Some(archive), Some(completed)) = (&mut model.archive, model.rb.completed()) {
+        archive.save(completed);
+    }
+    for change in ui::update_settings_ui(&mut model.ui, &mut model.gallery) {
+        match change {
+            ui::Change::Detector(detector_config) => {
+                model.detectors = fft::Detectors::new(&detector_config);
+            }
+            ui::Change::Input(input) => {
+                let channels = model.ui.profile.channels();
+                match recorder::open_input(channels, &input) {
+                    Ok(live) => {
+                        model.feeder = Feeder::new(Box::new(live), 1.0);
+                        model.ui.profile.input = input;
+                    }
+                    Err(e) => println!("Failed to open input: {}", e),
+                }
+            }
+        }
+    }
     simple_shader::update(app, model);
-
-    // TODO: fix update ui
-    ui::update_settings_ui(&mut model.ui);
 }
 
 fn view(app: &App, model: &Model, frame: Frame) {
     frame.clear(BLUE);
     render_shaders(model, &frame, app.main_window().device());
 
-    // ui::show(model, &frame);
+    ui::show(model, &frame);
     app.show_fps(&frame);
 }
 
+// Left and right browse the gallery, G tiles it as a grid
+fn key_pressed(_app: &App, model: &mut Model, key: Key) {
+    if model.ui.egui.ctx().wants_keyboard_input() {
+        return;
+    }
+    match key {
+        Key::Left => model.gallery.older(),
+        Key::Right => model.gallery.newer(),
+        Key::G => model.gallery.grid = !model.gallery.grid,
+        _ => {}
+    }
+}
+
 fn raw_window_event(_app: &App, model: &mut Model, event: &nannou::winit::event::WindowEvent) {
     ui::raw_event(_app, model, event);
 }
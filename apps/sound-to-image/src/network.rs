@@ -0,0 +1,82 @@
+// Audio streamed over TCP, for when the sound is played somewhere the app can't hear it. Listens
+// for one connection at a time and takes whatever is sent as interleaved little-endian f32
+// samples at the rate the images were encoded at, e.g. from
+//
+//     sox image.wav -t f32 -e floating-point -L - | nc localhost 7878
+use super::source::{self, AudioSource, Incoming};
+use std::io::Read;
+use std::net::TcpListener;
+use std::thread;
+
+pub struct NetworkSource {
+    address: String,
+    channels: usize,
+    incoming: Incoming,
+}
+
+impl NetworkSource {
+    pub fn listen(address: &str, channels: usize, sample_rate: u32) -> Self {
+        let listener = TcpListener::bind(address).expect("Failed to listen for audio");
+        let (mut sender, incoming) = source::incoming(channels, sample_rate);
+        sender.set_sample_rate(sample_rate);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        println!("Failed to accept audio connection: {}", e);
+                        continue;
+                    }
+                };
+                println!("audio connection from {:?}", stream.peer_addr());
+                let mut bytes = [0u8; 4096];
+                // Frames can be split across reads
+                let frame_len = channels * 4;
+                let mut pending = Vec::new();
+                let mut samples = Vec::new();
+                loop {
+                    let len = match stream.read(&mut bytes) {
+                        Ok(0) | Err(_) => break,
+                        Ok(len) => len,
+                    };
+                    pending.extend_from_slice(&bytes[..len]);
+                    let complete = pending.len() / frame_len * frame_len;
+                    samples.clear();
+                    samples.extend(pending[..complete].chunks(4).map(|sample| {
+                        f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]])
+                    }));
+                    sender.push(&samples);
+                    pending.drain(..complete);
+                }
+                println!("audio connection closed");
+            }
+        });
+        NetworkSource {
+            address: address.to_string(),
+            channels,
+            incoming,
+        }
+    }
+}
+
+impl AudioSource for NetworkSource {
+    fn sample_rate(&self) -> u32 {
+        self.incoming.sample_rate()
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn read(&mut self, frames: usize, buffer: &mut Vec<f32>) -> usize {
+        self.incoming.read(frames, buffer)
+    }
+
+    fn is_live(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        format!("the network on {}", self.address)
+    }
+}
@@ -0,0 +1,199 @@
+// Everything worth keeping between launches, so each venue's tuning survives a restart. A
+// profile file holds any number of named presets, as TOML or, if the file name ends in .json,
+// as JSON:
+//
+//     preset = "gallery"
+//
+//     [presets.gallery]
+//     width = 320
+//     height = 240
+//
+//     [presets.gallery.input]
+//     device = "USB Audio"
+//     sample_rate = 48000
+//
+//     [presets.gallery.shader]
+//     contrast = 1.8
+//     colour_map = "heat"
+//
+//     [presets.gallery.detector]
+//     threshold_db = 25.0
+//
+// Anything left out falls back to the defaults.
+use super::fft::DetectorConfig;
+use super::gallery;
+use super::recorder::InputConfig;
+use super::ui::Settings;
+use codec::{Mode, Modulation};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub mode: Mode,
+    // Defaults to the channels the mode needs
+    pub channels: Option<usize>,
+    pub width: u32,
+    pub height: u32,
+    pub row_sync: bool,
+    pub modulation: Modulation,
+    // Completed images kept for browsing
+    pub gallery: usize,
+    pub input: InputConfig,
+    pub shader: Settings,
+    pub detector: DetectorConfig,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            mode: Mode::Luminance,
+            channels: None,
+            width: codec::DEFAULT_WIDTH,
+            height: codec::DEFAULT_HEIGHT,
+            row_sync: false,
+            modulation: Modulation::Raw,
+            gallery: 9,
+            input: InputConfig::default(),
+            shader: Settings::default(),
+            detector: DetectorConfig::default(),
+        }
+    }
+}
+
+impl Profile {
+    pub fn channels(&self) -> usize {
+        self.channels.unwrap_or(self.mode.channels() as usize)
+    }
+
+    // Why the capture can't work with these settings, if it can't
+    pub fn validate(&self) -> Result<(), String> {
+        let channels = self.channels();
+        let needed = self.mode.channels() as usize;
+        if channels < needed {
+            return Err(format!(
+                "{} mode needs at least {} channels",
+                self.mode, needed
+            ));
+        }
+        if self.modulation == Modulation::Fsk && channels != needed {
+            return Err(format!(
+                "fsk images can't be shown as separate planes, {} mode needs exactly {} channels",
+                self.mode, needed
+            ));
+        }
+        self.detector.validate()?;
+        if self.gallery > gallery::max_layers() {
+            return Err(format!(
+                "the gallery can keep at most {} images",
+                gallery::max_layers()
+            ));
+        }
+        let max_size = gallery::max_layer_size();
+        if self.width as usize * channels > max_size as usize || self.height > max_size {
+            return Err(format!(
+                "{}x{} images with {} channels don't fit the gallery, it holds at most {} samples \
+                 per row and {} rows",
+                self.width, self.height, channels, max_size, max_size
+            ));
+        }
+        Ok(())
+    }
+
+    // Whether switching to `other` needs a restart, because the image size, how it is decoded or
+    // how many images are kept would change
+    pub fn needs_restart(&self, other: &Profile) -> bool {
+        self.mode != other.mode
+            || self.channels() != other.channels()
+            || self.width != other.width
+            || self.height != other.height
+            || self.row_sync != other.row_sync
+            || self.modulation != other.modulation
+            || self.gallery != other.gallery
+            || self.detector.sample_rate != other.detector.sample_rate
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Presets {
+    // Used when no preset is picked on the command line, the last one saved from the UI
+    pub preset: Option<String>,
+    pub presets: BTreeMap<String, Profile>,
+}
+
+pub struct ProfileFile {
+    pub path: String,
+    pub presets: Presets,
+}
+
+impl ProfileFile {
+    // Starts out empty if the file doesn't exist yet, saving creates it
+    pub fn load(path: &str) -> Result<Self, String> {
+        let presets = if Path::new(path).exists() {
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read profile {}: {}", path, e))?;
+            let parsed = if is_json(path) {
+                serde_json::from_str(&text).map_err(|e| e.to_string())
+            } else {
+                toml::from_str(&text).map_err(|e| e.to_string())
+            };
+            parsed.map_err(|e| format!("failed to parse profile {}: {}", path, e))?
+        } else {
+            Presets::default()
+        };
+        Ok(ProfileFile {
+            path: path.to_string(),
+            presets,
+        })
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.presets.presets.keys()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.presets.presets.get(name)
+    }
+
+    // `name`, or the default preset if it is None. Fails with the presets there are if there is
+    // no such preset
+    pub fn select(&self, name: Option<&str>) -> Result<Option<(&String, &Profile)>, String> {
+        let Some(name) = name.or(self.presets.preset.as_deref()) else {
+            return Ok(None);
+        };
+        match self.presets.presets.get_key_value(name) {
+            Some(preset) => Ok(Some(preset)),
+            None => {
+                let names: Vec<&str> = self.names().map(String::as_str).collect();
+                Err(format!(
+                    "no preset {:?} in {}, the presets there are: {}",
+                    name,
+                    self.path,
+                    names.join(", ")
+                ))
+            }
+        }
+    }
+
+    // Stores `profile` as `name` and makes it the default for the next launch
+    pub fn save(&mut self, name: &str, profile: Profile) -> io::Result<()> {
+        self.presets.presets.insert(name.to_string(), profile);
+        self.presets.preset = Some(name.to_string());
+        let text = if is_json(&self.path) {
+            serde_json::to_string_pretty(&self.presets).expect("Failed to serialise profile")
+        } else {
+            toml::to_string_pretty(&self.presets).expect("Failed to serialise profile")
+        };
+        std::fs::write(&self.path, text)
+    }
+}
+
+fn is_json(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
@@ -1,46 +1,438 @@
-use super::{HEIGHT, WIDTH};
+use super::drift::{self, Resampler};
+use super::source::{self, AudioSource, Incoming, IncomingSender};
+use codec::fsk::Demodulator;
+use codec::sync::{self, RowAligner};
 use nannou_audio as audio;
-use ringbuf::{Rb, StaticRb};
-use std::sync::{Arc, Mutex};
+use ringbuf::{HeapRb, Rb};
+use serde::{Deserialize, Serialize};
 
-pub const RB_SIZE: usize = WIDTH * HEIGHT;
+pub type RecorderInStream = audio::Stream<RecorderModel>;
 
-pub type AppAudioBuffer = Arc<Mutex<StaticRb<f32, RB_SIZE>>>;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureState {
+    // No start signal heard yet, the image wraps around whenever it fills up
+    FreeRunning,
+    // A start signal was heard, the image fills up until it is complete
+    Recording,
+    // The image is held until the next start signal
+    Complete,
+}
 
-pub type RecorderInStream = audio::Stream<RecorderModel>;
+// Turns the audio after the start signal into image frames, for images that aren't simply one
+// frame per pixel
+pub enum Decoder {
+    RowSync(RowAligner),
+    Fsk(Demodulator),
+}
+
+impl Decoder {
+    fn reset(&mut self) {
+        match self {
+            Decoder::RowSync(row_aligner) => row_aligner.reset(),
+            Decoder::Fsk(demodulator) => demodulator.reset(),
+        }
+    }
+
+    fn push(&mut self, frame: &[f32], output: &mut Vec<f32>) {
+        match self {
+            Decoder::RowSync(row_aligner) => row_aligner.push(frame, output),
+            // Mono, the first channel is enough
+            Decoder::Fsk(demodulator) => demodulator.push(frame[0], output),
+        }
+    }
+
+    // Frames the audio of an image takes up, for an image of `frames` frames
+    fn stream_frames(&self, frames: usize) -> usize {
+        match self {
+            Decoder::RowSync(row_aligner) => {
+                let width = row_aligner.width();
+                frames / width as usize * sync::frames_per_row(width)
+            }
+            Decoder::Fsk(demodulator) => demodulator.stream_len(),
+        }
+    }
+}
+
+// Everything captured so far, built up on the render thread from whatever the source handed over
+pub struct Capture {
+    // Holds one image worth of frames, with `channels` samples interleaved per frame. Depending
+    // on the mode the channels are either the colour channels of one image or separate planes
+    pub image: HeapRb<f32>,
+    // The latest frames, interleaved like `image`, for the signal detectors. It keeps running
+    // while a complete image is held
+    pub monitor: HeapRb<f32>,
+    // Frames pushed to `monitor` since the detectors last looked at it
+    pub fresh: usize,
+    pub state: CaptureState,
+    channels: usize,
+    // Brings the input to the rate the images were encoded at
+    resampler: Resampler,
+    resampled: Vec<f32>,
+    sample_rate: u32,
+    input_rate: Option<u32>,
+    // Frames since the image started, kept counting while it is held to measure drift
+    frames_since_start: usize,
+    // Whether the image started on the exact end of the start signal
+    aligned: bool,
+    // Decodes the image while recording, if it isn't raw frames
+    decoder: Option<Decoder>,
+    decoded: Vec<f32>,
+    // Frames an image takes up in the stream
+    expected_frames: usize,
+    // Samples at the front of `image` the GPU has already, see `take_new_samples`
+    uploaded: usize,
+    upload: Vec<f32>,
+    // Images completed so far
+    images: usize,
+    // The last image completed, until the renderer takes it to hold on screen
+    completed: Option<CompletedImage>,
+}
+
+pub struct CompletedImage {
+    // Counts up with every image, an image straightened after it completed keeps its number
+    pub number: usize,
+    pub samples: Vec<f32>,
+}
+
+impl Capture {
+    pub fn new(
+        channels: usize,
+        frames: usize,
+        monitor_len: usize,
+        sample_rate: u32,
+        decoder: Option<Decoder>,
+    ) -> Self {
+        let expected_frames = decoder
+            .as_ref()
+            .map_or(frames, |decoder| decoder.stream_frames(frames));
+        Capture {
+            image: HeapRb::new(frames * channels),
+            monitor: HeapRb::new(monitor_len * channels),
+            fresh: 0,
+            state: CaptureState::FreeRunning,
+            channels,
+            resampler: Resampler::new(channels),
+            resampled: Vec::new(),
+            sample_rate,
+            input_rate: None,
+            frames_since_start: 0,
+            aligned: false,
+            decoder,
+            decoded: Vec::new(),
+            expected_frames,
+            uploaded: 0,
+            upload: Vec::new(),
+            images: 0,
+            completed: None,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    // Throws away whatever was captured and starts a new image
+    pub fn start(&mut self) {
+        self.clear_image();
+        self.state = CaptureState::Recording;
+        self.frames_since_start = 0;
+        self.aligned = false;
+        if let Some(decoder) = self.decoder.as_mut() {
+            decoder.reset();
+        }
+    }
+
+    // Starts a new image from the last `frames` frames of the monitor, once it is known exactly
+    // where the image began
+    pub fn start_from_monitor(&mut self, frames: usize) {
+        self.start();
+        let skip = self.monitor.len().saturating_sub(frames * self.channels);
+        let recent: Vec<f32> = self.monitor.iter().skip(skip).copied().collect();
+        for frame in recent.chunks(self.channels) {
+            self.push_image_frame(frame);
+        }
+        self.frames_since_start = frames;
+        self.aligned = true;
+    }
+
+    pub fn finish(&mut self) {
+        if self.state != CaptureState::Complete {
+            println!("image complete");
+            if let Some(Decoder::Fsk(demodulator)) = &self.decoder {
+                if demodulator.failed_blocks() > 0 {
+                    println!(
+                        "{} blocks had too many errors to correct",
+                        demodulator.failed_blocks()
+                    );
+                }
+            }
+            self.state = CaptureState::Complete;
+            self.images += 1;
+            self.complete_image();
+        }
+    }
+
+    // Called once the end signal was located, it began `frames` frames ago. Comparing the
+    // image's length against what it should have been straightens it and tunes the resampler
+    // for the next one
+    pub fn end_at(&mut self, frames: usize) {
+        self.finish();
+        if !self.aligned {
+            return;
+        }
+        self.aligned = false;
+
+        let measured = self.frames_since_start.saturating_sub(frames);
+        let expected = self.expected_frames;
+        if !self.resampler.correct(measured, expected) {
+            println!("ignoring drift, image was {} frames long", measured);
+            return;
+        }
+        println!(
+            "drift {:+.0} ppm, resampling by {:.6}",
+            (measured as f64 / expected as f64 - 1.0) * 1e6,
+            self.resampler.drift()
+        );
+
+        // Decoded images are lined up already
+        if self.decoder.is_some() {
+            return;
+        }
+        let captured: Vec<f32> = self.image.iter().copied().collect();
+        let stretched = drift::stretch(&captured, self.channels, measured, expected);
+        self.clear_image();
+        self.image.push_iter(&mut stretched.into_iter());
+        // The straightened image replaces the one completed before
+        self.complete_image();
+    }
+
+    pub fn set_input_rate(&mut self, input_rate: u32) {
+        if self.input_rate != Some(input_rate) {
+            println!(
+                "input rate {} Hz, images at {} Hz",
+                input_rate, self.sample_rate
+            );
+            self.resampler.set_rates(input_rate, self.sample_rate);
+            self.input_rate = Some(input_rate);
+        }
+    }
+
+    pub fn push_frame(&mut self, frame: &[f32]) {
+        let mut resampled = std::mem::take(&mut self.resampled);
+        self.resampler.process(frame, &mut resampled);
+        for frame in resampled.chunks(self.channels) {
+            self.push_resampled_frame(frame);
+        }
+        resampled.clear();
+        self.resampled = resampled;
+    }
+
+    fn push_resampled_frame(&mut self, frame: &[f32]) {
+        self.frames_since_start += 1;
+        for sample in frame.iter().take(self.channels) {
+            self.monitor.push_overwrite(*sample);
+        }
+        self.fresh += 1;
+
+        if self.image.len() == self.image.capacity() {
+            match self.state {
+                CaptureState::FreeRunning => {
+                    println!("rb full, emptying");
+                    self.images += 1;
+                    self.complete_image();
+                    self.clear_image();
+                }
+                CaptureState::Recording => self.finish(),
+                CaptureState::Complete => {}
+            }
+        }
+        if self.state == CaptureState::Complete {
+            return;
+        }
+        self.push_image_frame(frame);
+    }
+
+    fn push_image_frame(&mut self, frame: &[f32]) {
+        // Before the start signal nothing says where the image begins, so there is nothing to decode
+        let decoder = match self.decoder.as_mut() {
+            Some(decoder) if self.state == CaptureState::Recording => decoder,
+            _ => {
+                for sample in frame.iter().take(self.channels) {
+                    self.push_image_sample(*sample);
+                }
+                return;
+            }
+        };
+        decoder.push(frame, &mut self.decoded);
+        let mut decoded = std::mem::take(&mut self.decoded);
+        for sample in decoded.drain(..) {
+            self.push_image_sample(sample);
+        }
+        self.decoded = decoded;
+    }
+
+    fn push_image_sample(&mut self, sample: f32) {
+        // Overwriting moves every sample along, so all of them have to go up again
+        if self.image.len() == self.image.capacity() {
+            self.uploaded = 0;
+        }
+        self.image.push_overwrite(sample);
+    }
+
+    fn complete_image(&mut self) {
+        self.completed = Some(CompletedImage {
+            number: self.images,
+            samples: self.image.iter().copied().collect(),
+        });
+    }
+
+    // The last image completed, without taking it from the renderer
+    pub fn completed(&self) -> Option<&CompletedImage> {
+        self.completed.as_ref()
+    }
+
+    pub fn take_completed(&mut self) -> Option<CompletedImage> {
+        self.completed.take()
+    }
+
+    fn clear_image(&mut self) {
+        self.image.clear();
+        self.uploaded = 0;
+    }
+
+    // Samples of the current image captured so far
+    pub fn write_cursor(&self) -> usize {
+        self.image.len()
+    }
+
+    // Returns where in the image the samples the GPU doesn't have yet start, and those samples.
+    // They count as uploaded from then on
+    pub fn take_new_samples(&mut self) -> (usize, &[f32]) {
+        let offset = self.uploaded;
+        self.upload.clear();
+        self.upload.extend(self.image.iter().skip(offset));
+        self.uploaded = self.image.len();
+        (offset, &self.upload)
+    }
+}
 
 pub struct RecorderModel {
-    rb: AppAudioBuffer,
+    sender: IncomingSender,
 }
 
-pub fn create() -> (AppAudioBuffer, RecorderInStream) {
-    let rb = Arc::new(Mutex::new(StaticRb::<f32, RB_SIZE>::default()));
-    let input_rb = rb.clone();
-    let recorder_model = RecorderModel { rb: input_rb };
+// The audio device, captured on the audio thread and read from the app's update
+pub struct LiveSource {
+    name: String,
+    channels: usize,
+    incoming: Incoming,
+    // Capturing stops when this is dropped
+    _in_stream: RecorderInStream,
+}
+
+impl AudioSource for LiveSource {
+    fn sample_rate(&self) -> u32 {
+        self.incoming.sample_rate()
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn read(&mut self, frames: usize, buffer: &mut Vec<f32>) -> usize {
+        self.incoming.read(frames, buffer)
+    }
+
+    fn is_live(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+// Which input to capture from and how. Anything left out is up to the system
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputConfig {
+    // Name or index of the input device, as listed by `sound-to-image devices`
+    pub device: Option<String>,
+    // Rate to open the device at, the capture resamples to the rate the images were encoded at
+    pub sample_rate: Option<u32>,
+    // Frames per buffer handed over by the device
+    pub buffer_size: Option<usize>,
+}
+
+// Starts capturing from `input`. Dropping the returned source stops it again, so switching
+// inputs is opening the new one and replacing the old source with it
+pub fn open_input(channels: usize, input: &InputConfig) -> Result<LiveSource, String> {
     let audio_host = audio::Host::new();
-    let in_stream = audio_host
+    let device = match &input.device {
+        Some(selector) => find_input_device(&audio_host, selector)?,
+        None => audio_host
+            .default_input_device()
+            .ok_or("No default input device")?,
+    };
+    // Room for the incoming audio depends on the rate the device really runs at
+    let sample_rate = match input.sample_rate {
+        Some(sample_rate) => sample_rate,
+        None => {
+            let config = device.default_input_config().map_err(|e| e.to_string())?;
+            config.sample_rate().0
+        }
+    };
+    let (sender, incoming) = source::incoming(channels, sample_rate);
+    let recorder_model = RecorderModel { sender };
+    let mut builder = audio_host
         .new_input_stream(recorder_model)
-        .capture(pass_in)
-        .build()
-        .unwrap();
-    in_stream.play().unwrap();
-    return (rb, in_stream);
+        .channels(channels)
+        .device(device)
+        .capture(pass_in);
+    if let Some(sample_rate) = input.sample_rate {
+        builder = builder.sample_rate(sample_rate);
+    }
+    if let Some(buffer_size) = input.buffer_size {
+        builder = builder.frames_per_buffer(buffer_size);
+    }
+    let in_stream = builder.build().map_err(|e| e.to_string())?;
+    in_stream.play().map_err(|e| e.to_string())?;
+    Ok(LiveSource {
+        name: input
+            .device
+            .as_deref()
+            .unwrap_or("the default input")
+            .to_string(),
+        channels,
+        incoming,
+        _in_stream: in_stream,
+    })
 }
 
-fn pass_in(model: &mut RecorderModel, buffer: &nannou_audio::Buffer) {
-    // if rb is full, empty it first
-    let mut rb = model.rb.lock().unwrap();
-    if rb.len() == RB_SIZE {
-        println!("rb full, emptying");
-        rb.clear();
+pub fn input_device_names() -> Vec<String> {
+    let audio_host = audio::Host::new();
+    match audio_host.input_devices() {
+        Ok(devices) => devices
+            .map(|device| device.name().unwrap_or_else(|_| "(unnamed)".to_string()))
+            .collect(),
+        Err(e) => {
+            println!("Failed to list input devices: {}", e);
+            Vec::new()
+        }
     }
-    buffer.frames().for_each(|frame| {
-        let ch1 = frame.get(0).unwrap();
-        rb.push_overwrite(*ch1);
-    });
 }
 
-pub fn collect_samples(rb: &AppAudioBuffer) -> Vec<f32> {
-    let rb = rb.lock().unwrap();
-    rb.iter().copied().collect()
+// `selector` is either the index of the device in `input_device_names` or its name
+fn find_input_device(audio_host: &audio::Host, selector: &str) -> Result<audio::Device, String> {
+    let mut devices = audio_host.input_devices().map_err(|e| e.to_string())?;
+    let device = match selector.parse::<usize>() {
+        Ok(index) => devices.nth(index),
+        Err(_) => devices.find(|device| device.name().is_ok_and(|name| name == selector)),
+    };
+    device.ok_or_else(|| format!("No input device {:?}", selector))
+}
+
+// Runs on the audio thread, so it only hands the buffer over and never waits on anything
+fn pass_in(model: &mut RecorderModel, buffer: &nannou_audio::Buffer) {
+    model.sender.set_sample_rate(buffer.sample_rate());
+    model.sender.push(buffer);
 }
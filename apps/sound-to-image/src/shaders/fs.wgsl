@@ -2,9 +2,28 @@ struct Uniforms {
     time: f32,
     window_width: f32,
     window_height: f32, // New fields
+    mode: u32, // 0: luminance, 1: rgb
+    channels: u32,
+    image_width: u32,
+    image_height: u32,
+    contrast: f32,
+    gain: f32,
+    offset: f32,
+    colour_map: u32, // 0: grey, 1: inverted, 2: heat, 3: viridis
+    write_cursor: u32, // samples captured so far, the rest of the buffer is left over
+    reveal: u32, // 1: pixels past write_cursor are drawn as background
+    scan_head: u32, // 1: glow where the image is being written while revealing
+    hold: f32, // how much of the held image is mixed in, 0 to 1
+    held_layer: u32, // layer of the gallery holding it
+    view: u32, // 0: live image, 1: the gallery image in shown_layer, 2: the gallery as a grid
+    shown_layer: u32,
+    gallery_len: u32, // images in the gallery
+    gallery_newest: u32, // layer of the newest image, the ones before it are in the layers below
+    gallery_layers: u32,
 };
 struct AudioData {
-    samples: array<f32, 250000>,
+    // Interleaved frames, one frame of `channels` samples per pixel
+    samples: array<f32>,
 };
 
 struct FragmentOutput {
@@ -14,30 +33,138 @@ struct FragmentOutput {
 @group(0) @binding(0) var<storage, read> audioData: AudioData;
 @group(0) @binding(1)
 var<uniform> uniforms: Uniforms;
+// The last completed images, one per layer, each laid out like audioData with one row of the
+// image per texture row
+@group(0) @binding(2) var gallery: texture_2d_array<f32>;
 
-@fragment
-fn main(@location(0) tex_coords: vec2<f32>) -> FragmentOutput {
-    // Calculate the 1D index from the 2D texture coordinates
-    let index = u32(tex_coords.y * 500.0) * 500u + u32(tex_coords.x * 500.0);
+// Reads from the gallery layer `layer` unless `live`
+fn sampleAt(index: u32, live: bool, layer: u32) -> f32 {
+    if (!live) {
+        let rowLength = uniforms.image_width * uniforms.channels;
+        let safeIndex = min(index, rowLength * uniforms.image_height - 1u);
+        let texel = vec2<i32>(i32(safeIndex % rowLength), i32(safeIndex / rowLength));
+        return textureLoad(gallery, texel, i32(layer), 0).r;
+    }
 
     // Ensure the index does not go out of bounds
-    let safeIndex = min(index, 249999u);
+    let safeIndex = min(index, arrayLength(&audioData.samples) - 1u);
+    return audioData.samples[safeIndex];
+}
 
+fn colorAt(index: u32, live: bool, layer: u32) -> f32 {
     // Get the sample value
-    let sampleValue = audioData.samples[safeIndex];
+    let sampleValue = sampleAt(index, live, layer);
 
-    // Normalize the sample value to (0.0 to 1.0) for color mapping
-    let colorValue = (sampleValue + 1.0) * 0.5;
+    // Normalize the sample value to (0.0 to 1.0) for color mapping (see codec::sample_to_brightness)
+    let colorValue = (sampleValue * uniforms.gain + 1.0) * 0.5 + uniforms.offset;
 
     // Adjust contrast
-    let contrastFactor: f32 = 2.2; // Example contrast factor, adjust as needed
-    let adjustedColorValue = (colorValue - 0.5) * contrastFactor + 0.5;
+    let adjustedColorValue = (colorValue - 0.5) * uniforms.contrast + 0.5;
 
     // Ensure the color value remains in the 0.0 to 1.0 range
-    let clampedColorValue = clamp(adjustedColorValue, 0.0, 1.0);
+    return clamp(adjustedColorValue, 0.0, 1.0);
+}
+
+fn colourMap(value: f32) -> vec3<f32> {
+    switch uniforms.colour_map {
+        case 1u: {
+            return vec3<f32>(1.0 - value);
+        }
+        case 2u: {
+            // Black through red and yellow to white
+            return clamp(vec3<f32>(value * 3.0, value * 3.0 - 1.0, value * 3.0 - 2.0), vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+        case 3u: {
+            // Polynomial fit of matplotlib's viridis
+            let c0 = vec3<f32>(0.2777, 0.0054, 0.3341);
+            let c1 = vec3<f32>(0.1051, 1.4046, 1.3846);
+            let c2 = vec3<f32>(-0.3309, 0.2148, 0.0951);
+            let c3 = vec3<f32>(-4.6342, -5.7991, -19.3324);
+            let c4 = vec3<f32>(6.2283, 14.1799, 56.6906);
+            let c5 = vec3<f32>(4.7764, -13.7451, -65.3530);
+            let c6 = vec3<f32>(-5.4355, 4.6459, 26.3124);
+            let colour = c0 + value * (c1 + value * (c2 + value * (c3 + value * (c4 + value * (c5 + value * c6)))));
+            return clamp(colour, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+        default: {
+            return vec3<f32>(value);
+        }
+    }
+}
+
+fn pixelColor(index: u32, plane: u32, live: bool, layer: u32) -> vec3<f32> {
+    if (uniforms.mode == 1u) {
+        return vec3<f32>(colorAt(index, live, layer), colorAt(index + 1u, live, layer), colorAt(index + 2u, live, layer));
+    }
+    return colourMap(colorAt(index + plane, live, layer));
+}
+
+// Shown where nothing was written yet while revealing
+const BACKGROUND = vec3<f32>(0.0, 0.0, 0.0);
+const SCAN_HEAD = vec3<f32>(1.0, 0.85, 0.5);
+
+// Brightens the pixels written last, fading out along the trail behind the write position
+fn scanHead(color: vec3<f32>, pixel: u32, written: u32) -> vec3<f32> {
+    let behind = f32(written - pixel);
+    let trail = max(f32(uniforms.image_width) / 8.0, 1.0);
+    let glow = exp(-behind / trail);
+    return mix(color, SCAN_HEAD, glow * 0.8);
+}
+
+@fragment
+fn main(@location(0) tex_coords: vec2<f32>) -> FragmentOutput {
+    // Where in which image the fragment is, the live one unless the gallery is being looked at
+    let live = uniforms.view == 0u;
+    var coords = tex_coords;
+    var layer = uniforms.shown_layer;
+    if (uniforms.view == 2u) {
+        // Tile the gallery, newest first in the same order as the pixels of an image
+        let columns = u32(ceil(sqrt(f32(uniforms.gallery_len))));
+        let rows = (uniforms.gallery_len + columns - 1u) / columns;
+        let grid = tex_coords * vec2<f32>(f32(columns), f32(rows));
+        let cell = min(u32(grid.y), rows - 1u) * columns + min(u32(grid.x), columns - 1u);
+        if (cell >= uniforms.gallery_len) {
+            return FragmentOutput(vec4<f32>(BACKGROUND, 1.0));
+        }
+        coords = fract(grid);
+        layer = (uniforms.gallery_newest + uniforms.gallery_layers - cell) % uniforms.gallery_layers;
+    }
+
+    // In luminance mode every channel is its own image plane, laid out side by side
+    var planes = uniforms.channels;
+    if (uniforms.mode == 1u) {
+        planes = 1u;
+    }
+    let planeX = coords.x * f32(planes);
+    let plane = min(u32(planeX), planes - 1u);
+
+    // Calculate the 1D pixel index from the 2D texture coordinates
+    let x = min(u32(fract(planeX) * f32(uniforms.image_width)), uniforms.image_width - 1u);
+    let y = min(u32(coords.y * f32(uniforms.image_height)), uniforms.image_height - 1u);
+    let pixel = y * uniforms.image_width + x;
+    let index = pixel * uniforms.channels;
+
+    var color = pixelColor(index, plane, live, layer);
+
+    if (live && uniforms.reveal == 1u) {
+        let pixels = uniforms.image_width * uniforms.image_height;
+        let written = uniforms.write_cursor / uniforms.channels;
+        let scanning = uniforms.scan_head == 1u && written < pixels;
+        if (pixel >= written) {
+            color = BACKGROUND;
+            // A faint line across the row being written
+            if (scanning && y == written / uniforms.image_width) {
+                color = mix(color, SCAN_HEAD, 0.15);
+            }
+        } else if (scanning) {
+            color = scanHead(color, pixel, written);
+        }
+    }
 
-    // Create the color vector
-    var color: vec3<f32> = vec3<f32>(clampedColorValue, clampedColorValue, clampedColorValue);
+    // Fade from the held image into the current one
+    if (live && uniforms.hold > 0.0) {
+        color = mix(color, pixelColor(index, plane, false, uniforms.held_layer), uniforms.hold);
+    }
 
     // Return the color as the fragment output
     return FragmentOutput(vec4<f32>(color, 1.0));
@@ -6,6 +6,24 @@ struct Uniforms {
     time: f32,
     window_width: f32,
     window_height: f32, // New fields
+    mode: u32,
+    channels: u32,
+    image_width: u32,
+    image_height: u32,
+    contrast: f32,
+    gain: f32,
+    offset: f32,
+    colour_map: u32, // 0: grey, 1: inverted, 2: heat, 3: viridis
+    write_cursor: u32, // samples captured so far, the rest of the buffer is left over
+    reveal: u32, // 1: pixels past write_cursor are drawn as background
+    scan_head: u32, // 1: glow where the image is being written while revealing
+    hold: f32, // how much of the held image is mixed in, 0 to 1
+    held_layer: u32, // layer of the gallery holding it
+    view: u32, // 0: live image, 1: the gallery image in shown_layer, 2: the gallery as a grid
+    shown_layer: u32,
+    gallery_len: u32, // images in the gallery
+    gallery_newest: u32, // layer of the newest image, the ones before it are in the layers below
+    gallery_layers: u32,
 };
 
 @group(0) @binding(1)
@@ -13,9 +31,15 @@ var<uniform> uniforms: Uniforms;
 
 @vertex
 fn main(@location(0) pos: vec2<f32>) -> VertexOutput {
+    // Luminance mode shows every channel as its own plane next to each other
+    var planes: f32 = f32(uniforms.channels);
+    if (uniforms.mode == 1u) {
+        planes = 1.0;
+    }
+
     // Calculate the NDC size based on the viewport size
-    let ndc_width: f32 = 500.0 / uniforms.window_width * 2.0;
-    let ndc_height: f32 = 500.0 / uniforms.window_height * 2.0;
+    let ndc_width: f32 = f32(uniforms.image_width) * planes / uniforms.window_width * 2.0;
+    let ndc_height: f32 = f32(uniforms.image_height) / uniforms.window_height * 2.0;
 
     // Scale position to maintain the quad size in NDC
     let scaled_x: f32 = pos.x * ndc_width;
@@ -3,8 +3,9 @@ use nannou::prelude::*;
 use nannou::wgpu::{self};
 use wgpu::*;
 
-use super::{recorder, AppAudioBuffer};
-use recorder::RB_SIZE;
+use super::gallery::Gallery;
+use super::recorder::{Capture, CaptureState};
+use super::ui::Settings;
 
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -32,6 +33,8 @@ pub struct SetupRenderPipelineParams<'a> {
     pub fs_desc: ShaderModuleDescriptor<'a>,
     pub sample_count: u32,
     pub uniforms: &'a Uniforms,
+    pub audio_buffer_len: usize,
+    pub gallery_layers: u32,
 }
 
 pub struct SetupRenderPipelineOutput {
@@ -41,6 +44,16 @@ pub struct SetupRenderPipelineOutput {
     pub uniforms: Uniforms,
     pub uniform_buffer: Buffer,
     pub audio_storage_buffer: Buffer,
+    // The last completed images, one per layer, see store_completed_image
+    pub gallery_texture: TextureHandle,
+    pub held: Option<Held>,
+}
+
+// When the newest image in the gallery completed and when it started fading out, in seconds of
+// app time
+pub struct Held {
+    since: f32,
+    fading_since: Option<f32>,
 }
 
 pub fn setup_render_pipeline(params: SetupRenderPipelineParams) -> SetupRenderPipelineOutput {
@@ -50,6 +63,8 @@ pub fn setup_render_pipeline(params: SetupRenderPipelineParams) -> SetupRenderPi
         fs_desc,
         sample_count,
         uniforms,
+        audio_buffer_len,
+        gallery_layers,
     } = params;
 
     let vs_mod = device.create_shader_module(vs_desc);
@@ -70,24 +85,52 @@ pub fn setup_render_pipeline(params: SetupRenderPipelineParams) -> SetupRenderPi
 
     let audio_storage_buffer = device.create_buffer_init(&BufferInitDescriptor {
         label: Some("Audio Storage Buffer"),
-        contents: bytemuck::cast_slice(&[0.0f32; RB_SIZE]),
+        contents: bytemuck::cast_slice(&vec![0.0f32; audio_buffer_len]),
         usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
     });
 
+    // Every layer holds an image's samples the same way as the storage buffer, a row of the
+    // image per row
+    let gallery_texture = device.create_texture(&TextureDescriptor {
+        label: Some("Gallery Texture"),
+        size: Extent3d {
+            width: uniforms.image_width * uniforms.channels,
+            height: uniforms.image_height,
+            depth_or_array_layers: gallery_layers,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::R32Float,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    let gallery_texture_view = gallery_texture.create_view(&TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2Array),
+        ..Default::default()
+    });
+
     let storage_dynamic = false;
     let storage_readonly = true;
     // Create the render pipeline.
     let bind_group_layout = BindGroupLayoutBuilder::new()
         .storage_buffer(ShaderStages::FRAGMENT, storage_dynamic, storage_readonly)
         .uniform_buffer(ShaderStages::VERTEX | ShaderStages::FRAGMENT, false)
+        .texture(
+            ShaderStages::FRAGMENT,
+            false,
+            TextureViewDimension::D2Array,
+            TextureSampleType::Float { filterable: false },
+        )
         .build(device);
 
-    let audio_buffer_size = (RB_SIZE as usize * std::mem::size_of::<f32>()) as wgpu::BufferAddress;
+    let audio_buffer_size = (audio_buffer_len * std::mem::size_of::<f32>()) as wgpu::BufferAddress;
     let buffer_size_bytes = std::num::NonZeroU64::new(audio_buffer_size).unwrap();
 
     let bind_group = BindGroupBuilder::new()
         .buffer_bytes(&audio_storage_buffer, 0, Some(buffer_size_bytes))
         .buffer::<Uniforms>(&uniform_buffer, 0..1)
+        .texture_view(&gallery_texture_view)
         .build(device, &bind_group_layout);
 
     let pipeline_layout = create_pipeline_layout(device, None, &[&bind_group_layout], &[]);
@@ -107,6 +150,8 @@ pub fn setup_render_pipeline(params: SetupRenderPipelineParams) -> SetupRenderPi
         uniforms: uniforms.clone(),
         uniform_buffer,
         audio_storage_buffer,
+        gallery_texture,
+        held: None,
     }
 }
 
@@ -161,46 +206,127 @@ pub fn update(app: &App, model: &mut Model) {
     model.shader_settings.uniforms.window_width = app.main_window().rect().w() * scale_factor;
     model.shader_settings.uniforms.window_height = app.main_window().rect().h() * scale_factor;
 
-    // Create a command encoder
-    let mut encoder =
-        app.main_window()
-            .device()
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Audio Storage Buffer Update Encoder"),
-            });
+    // Picked up by render_shaders, which copies the uniforms to the GPU every frame
+    let settings = &model.ui.settings;
+    model.shader_settings.uniforms.contrast = settings.contrast;
+    model.shader_settings.uniforms.gain = settings.gain;
+    model.shader_settings.uniforms.offset = settings.offset;
+    model.shader_settings.uniforms.colour_map = settings.colour_map.index();
+    model.shader_settings.uniforms.reveal = settings.reveal as u32;
+    model.shader_settings.uniforms.scan_head = settings.scan_head as u32;
 
-    // Update the audio storage buffer with the latest samples from the ring buffer
+    // Only what was captured since the last frame goes up to the GPU
     update_audio_storage_buffer(
-        app.main_window().device(),
-        &mut encoder,
+        app.main_window().queue(),
         &model.shader_settings.audio_storage_buffer,
-        &model.rb,
+        &mut model.rb,
     );
+    model.shader_settings.uniforms.write_cursor = model.rb.write_cursor() as u32;
 
-    // Submit the commands to the GPU
-    app.main_window()
-        .queue()
-        .submit(std::iter::once(encoder.finish()));
+    store_completed_image(
+        app.main_window().queue(),
+        &mut model.shader_settings,
+        &mut model.rb,
+        &mut model.gallery,
+        settings,
+        app.time,
+    );
+
+    let gallery = &model.gallery;
+    let uniforms = &mut model.shader_settings.uniforms;
+    uniforms.view = if gallery.grid && !gallery.is_empty() {
+        2
+    } else if let Some(back) = gallery.shown {
+        uniforms.shown_layer = gallery.layer(back) as u32;
+        1
+    } else {
+        0
+    };
+    uniforms.gallery_len = gallery.len() as u32;
+    uniforms.gallery_layers = gallery.layers() as u32;
+    if !gallery.is_empty() {
+        uniforms.gallery_newest = gallery.layer(0) as u32;
+    }
 }
-fn update_audio_storage_buffer(
-    device: &Device,
-    encoder: &mut CommandEncoder,
-    audio_storage_buffer: &Buffer,
-    rb: &AppAudioBuffer,
+
+// Copies a newly completed image to the gallery and works out how much of it covers the current
+// image. It is held for `Settings::hold` seconds, then fades into the next image once that one
+// started
+fn store_completed_image(
+    queue: &Queue,
+    shader_settings: &mut SetupRenderPipelineOutput,
+    capture: &mut Capture,
+    gallery: &mut Gallery,
+    settings: &Settings,
+    time: f32,
 ) {
-    // Lock the ring buffer and collect the samples into a Vec
-    let samples: Vec<f32> = recorder::collect_samples(rb);
-
-    // Create a temporary buffer with the new audio samples
-    let temp_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("Temp Audio Buffer"),
-        contents: bytemuck::cast_slice(&samples),
-        usage: wgpu::BufferUsages::COPY_SRC,
-    });
+    if let Some(completed) = capture.take_completed() {
+        let layer = gallery.add(completed.number) as u32;
+        let texture = &shader_settings.gallery_texture;
+        let (width, height) = (texture.width(), texture.height());
+        let mut samples = completed.samples;
+        // Decoded images can come up short, the rest stays blank
+        samples.resize((width * height) as usize, 0.0);
+        queue.write_texture(
+            ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer,
+                },
+                aspect: TextureAspect::All,
+            },
+            bytemuck::cast_slice(&samples),
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width * std::mem::size_of::<f32>() as u32),
+                rows_per_image: Some(height),
+            },
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        shader_settings.uniforms.held_layer = layer;
+        shader_settings.held = Some(Held {
+            since: time,
+            fading_since: None,
+        });
+    }
 
-    // Calculate the size of the data to copy
-    let data_size = (samples.len() * std::mem::size_of::<f32>()) as BufferAddress;
+    let hold = match shader_settings.held.as_mut() {
+        Some(held) => {
+            let next_started = capture.state != CaptureState::Complete;
+            if held.fading_since.is_none() && time - held.since >= settings.hold && next_started {
+                held.fading_since = Some(time);
+            }
+            match held.fading_since {
+                Some(fading_since) => 1.0 - (time - fading_since) / settings.crossfade.max(1e-3),
+                None => 1.0,
+            }
+        }
+        None => 0.0,
+    };
+    if hold <= 0.0 {
+        shader_settings.held = None;
+    }
+    shader_settings.uniforms.hold = hold.max(0.0);
+}
 
-    // Copy the data from the temporary buffer to the audio_storage_buffer
-    encoder.copy_buffer_to_buffer(&temp_buffer, 0, audio_storage_buffer, 0, data_size);
+fn update_audio_storage_buffer(
+    queue: &Queue,
+    audio_storage_buffer: &Buffer,
+    capture: &mut Capture,
+) {
+    let (offset, samples) = capture.take_new_samples();
+    if samples.is_empty() {
+        return;
+    }
+    // The storage buffer holds the image in the same order as the ring buffer, so the new
+    // samples go right where they are in it
+    let offset = (offset * std::mem::size_of::<f32>()) as BufferAddress;
+    queue.write_buffer(audio_storage_buffer, offset, bytemuck::cast_slice(samples));
 }
@@ -0,0 +1,265 @@
+// Where the captured audio comes from. Every source, whether it is the audio device, a file, a
+// generated test signal or a network stream, is read by a `Feeder` from the app's update, so the
+// capture, the detectors and the decoders see exactly the same thing whichever it is. Tests can
+// drive a `Feeder` with a made up clock and a fake source without any audio hardware.
+use super::recorder::Capture;
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub trait AudioSource {
+    // Rate the source's frames are at, 0 if it doesn't know yet
+    fn sample_rate(&self) -> u32;
+
+    fn channels(&self) -> usize;
+
+    // Appends up to `frames` interleaved frames to `buffer` and returns how many it appended
+    fn read(&mut self, frames: usize, buffer: &mut Vec<f32>) -> usize;
+
+    // Live sources hand over whatever arrived since the last read. The others have all their
+    // audio at hand and are held back to real time
+    fn is_live(&self) -> bool;
+
+    // Whether nothing more is ever going to come
+    fn is_finished(&self) -> bool {
+        false
+    }
+
+    fn name(&self) -> String;
+}
+
+pub struct Feeder {
+    source: Box<dyn AudioSource>,
+    // How much faster than real time a source that isn't live is read
+    speed: f32,
+    started: Instant,
+    frames_read: usize,
+    buffer: Vec<f32>,
+    frame: Vec<f32>,
+    finished: bool,
+}
+
+impl Feeder {
+    pub fn new(source: Box<dyn AudioSource>, speed: f32) -> Self {
+        println!("reading from {}", source.name());
+        Feeder {
+            source,
+            speed,
+            started: Instant::now(),
+            frames_read: 0,
+            buffer: Vec::new(),
+            frame: Vec::new(),
+            finished: false,
+        }
+    }
+
+    pub fn feed(&mut self, capture: &mut Capture) {
+        self.feed_at(self.started.elapsed(), capture);
+    }
+
+    // Pushes everything the source has for `elapsed` after it started into the capture
+    pub fn feed_at(&mut self, elapsed: Duration, capture: &mut Capture) {
+        let frames = if self.source.is_live() {
+            usize::MAX
+        } else {
+            let due = elapsed.as_secs_f64() * self.source.sample_rate() as f64 * self.speed as f64;
+            (due as usize).saturating_sub(self.frames_read)
+        };
+        self.buffer.clear();
+        let read = self.source.read(frames, &mut self.buffer);
+        self.frames_read += read;
+
+        if read > 0 {
+            capture.set_input_rate(self.source.sample_rate());
+        }
+        // Channels the source doesn't have stay silent
+        let source_channels = self.source.channels();
+        self.frame.resize(capture.channels(), 0.0);
+        for source_frame in self.buffer.chunks(source_channels) {
+            for (channel, sample) in self.frame.iter_mut().enumerate() {
+                *sample = source_frame.get(channel).copied().unwrap_or(0.0);
+            }
+            capture.push_frame(&self.frame);
+        }
+
+        if self.source.is_finished() && !self.finished {
+            println!("finished reading from {}", self.source.name());
+            self.finished = true;
+        }
+    }
+}
+
+// Audio handed over by another thread as it arrives, for live sources. The two ends are a lock
+// free ring buffer split in two, so the thread handing audio over never waits on the render
+// thread. Holds a couple of seconds at the rate the audio comes in at, whatever doesn't fit
+// anymore is dropped and counted
+pub struct IncomingSender {
+    samples: HeapProducer<f32>,
+    channels: usize,
+    sample_rate: Arc<AtomicU32>,
+    dropped: Arc<AtomicUsize>,
+}
+
+pub struct Incoming {
+    samples: HeapConsumer<f32>,
+    channels: usize,
+    sample_rate: Arc<AtomicU32>,
+    dropped: Arc<AtomicUsize>,
+}
+
+const INCOMING_SECONDS: usize = 2;
+
+pub fn incoming(channels: usize, sample_rate: u32) -> (IncomingSender, Incoming) {
+    let capacity = INCOMING_SECONDS * sample_rate as usize * channels;
+    let (producer, consumer) = HeapRb::new(capacity).split();
+    let sample_rate = Arc::new(AtomicU32::new(0));
+    let dropped = Arc::new(AtomicUsize::new(0));
+    let sender = IncomingSender {
+        samples: producer,
+        channels,
+        sample_rate: sample_rate.clone(),
+        dropped: dropped.clone(),
+    };
+    let incoming = Incoming {
+        samples: consumer,
+        channels,
+        sample_rate,
+        dropped,
+    };
+    (sender, incoming)
+}
+
+impl IncomingSender {
+    pub fn set_sample_rate(&self, sample_rate: u32) {
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    // Hands over the whole frames in `samples` that fit, dropping the rest
+    pub fn push(&mut self, samples: &[f32]) {
+        let fits = self.samples.free_len() / self.channels * self.channels;
+        let len = samples.len().min(fits);
+        self.samples.push_slice(&samples[..len]);
+        if len < samples.len() {
+            let frames = (samples.len() - len) / self.channels;
+            self.dropped.fetch_add(frames, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Incoming {
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate.load(Ordering::Relaxed)
+    }
+
+    // Appends up to `frames` frames of what arrived so far to `buffer`
+    pub fn read(&mut self, frames: usize, buffer: &mut Vec<f32>) -> usize {
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            println!("dropped {} frames, the app fell behind the audio", dropped);
+        }
+        let frames = frames.min(self.samples.len() / self.channels);
+        let start = buffer.len();
+        buffer.resize(start + frames * self.channels, 0.0);
+        self.samples.pop_slice(&mut buffer[start..]);
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ringbuf::Rb;
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    // Has `frames` mono frames of 0.5 at hand
+    struct FakeSource {
+        frames: usize,
+        live: bool,
+    }
+
+    impl AudioSource for FakeSource {
+        fn sample_rate(&self) -> u32 {
+            SAMPLE_RATE
+        }
+
+        fn channels(&self) -> usize {
+            1
+        }
+
+        fn read(&mut self, frames: usize, buffer: &mut Vec<f32>) -> usize {
+            let frames = frames.min(self.frames);
+            self.frames -= frames;
+            buffer.extend(std::iter::repeat_n(0.5, frames));
+            frames
+        }
+
+        fn is_live(&self) -> bool {
+            self.live
+        }
+
+        fn is_finished(&self) -> bool {
+            !self.live && self.frames == 0
+        }
+
+        fn name(&self) -> String {
+            "fake".to_string()
+        }
+    }
+
+    fn feeder(frames: usize, live: bool, speed: f32) -> Feeder {
+        Feeder::new(Box::new(FakeSource { frames, live }), speed)
+    }
+
+    fn capture(channels: usize) -> Capture {
+        Capture::new(channels, 10_000, 100, SAMPLE_RATE, None)
+    }
+
+    #[test]
+    fn reads_at_real_time() {
+        let mut feeder = feeder(10_000, false, 1.0);
+        let mut capture = capture(1);
+        feeder.feed_at(Duration::from_millis(500), &mut capture);
+        assert_eq!(feeder.frames_read, 500);
+        feeder.feed_at(Duration::from_millis(750), &mut capture);
+        assert_eq!(feeder.frames_read, 750);
+    }
+
+    #[test]
+    fn reads_faster_with_speed() {
+        let mut feeder = feeder(10_000, false, 4.0);
+        feeder.feed_at(Duration::from_millis(500), &mut capture(1));
+        assert_eq!(feeder.frames_read, 2000);
+    }
+
+    #[test]
+    fn live_sources_hand_over_everything() {
+        let mut feeder = feeder(1234, true, 1.0);
+        feeder.feed_at(Duration::ZERO, &mut capture(1));
+        assert_eq!(feeder.frames_read, 1234);
+    }
+
+    #[test]
+    fn fills_missing_channels_with_silence() {
+        let mut feeder = feeder(10_000, false, 1.0);
+        let mut capture = capture(3);
+        feeder.feed_at(Duration::from_millis(500), &mut capture);
+        let monitor: Vec<f32> = capture.monitor.iter().copied().collect();
+        assert!(!monitor.is_empty());
+        for frame in monitor.chunks(3) {
+            assert_eq!(frame, [0.5, 0.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn stops_at_the_end_of_a_finished_source() {
+        let mut feeder = feeder(300, false, 1.0);
+        let mut capture = capture(1);
+        feeder.feed_at(Duration::from_secs(1), &mut capture);
+        assert_eq!(feeder.frames_read, 300);
+        assert!(feeder.finished);
+        feeder.feed_at(Duration::from_secs(2), &mut capture);
+        assert_eq!(feeder.frames_read, 300);
+    }
+}
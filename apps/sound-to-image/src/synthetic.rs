@@ -0,0 +1,206 @@
+// A test signal made up on the spot: a test pattern encoded the way image-to-sound would, between
+// the start and end signals, over and over with the pattern moving along a little every time.
+// Needs neither a microphone nor a file to show whether the whole chain works.
+use super::fft::DetectorConfig;
+use super::profile::Profile;
+use super::source::AudioSource;
+use codec::{Metadata, Modulation};
+use image::{DynamicImage, Rgb, RgbImage};
+
+// Quiet between the images, in seconds
+const GAP: f32 = 0.5;
+
+pub struct Synthetic {
+    profile: Profile,
+    channels: usize,
+    // One image with its signals and the gap after it
+    cycle: Vec<f32>,
+    cycles: usize,
+    position: usize,
+}
+
+impl Synthetic {
+    pub fn new(profile: &Profile) -> Self {
+        let metadata = Metadata {
+            row_sync: profile.row_sync,
+            modulation: profile.modulation,
+            ..Metadata::new(profile.width, profile.height, profile.mode)
+        };
+        let mut synthetic = Synthetic {
+            profile: profile.clone(),
+            channels: metadata.channels() as usize,
+            cycle: Vec::new(),
+            cycles: 0,
+            position: 0,
+        };
+        synthetic.next_cycle();
+        synthetic
+    }
+
+    fn next_cycle(&mut self) {
+        let Profile {
+            mode,
+            width,
+            height,
+            ..
+        } = self.profile;
+        let img = test_pattern(width, height, self.cycles);
+        let mut samples = match self.profile.modulation {
+            Modulation::Raw => codec::encode(&img, mode),
+            Modulation::Fsk => codec::fsk::modulate(&codec::pixel_values(&img, mode)),
+        };
+        if self.profile.row_sync {
+            samples = codec::sync::insert_markers(&samples, width, self.channels);
+        }
+
+        let DetectorConfig {
+            sample_rate,
+            start_signal,
+            end_signal,
+            ..
+        } = &self.profile.detector;
+        let sample_rate = *sample_rate as u32;
+        let on_every_channel = |signal: Vec<f32>| -> Vec<f32> {
+            signal
+                .into_iter()
+                .flat_map(|s| std::iter::repeat_n(s, self.channels))
+                .collect()
+        };
+        self.cycle = on_every_channel(start_signal.synthesize(sample_rate));
+        self.cycle.extend(samples);
+        self.cycle
+            .extend(on_every_channel(end_signal.synthesize(sample_rate)));
+        let gap = (GAP * sample_rate as f32) as usize * self.channels;
+        self.cycle.extend(std::iter::repeat_n(0.0, gap));
+        self.cycles += 1;
+        self.position = 0;
+    }
+}
+
+impl AudioSource for Synthetic {
+    fn sample_rate(&self) -> u32 {
+        self.profile.detector.sample_rate as u32
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn read(&mut self, frames: usize, buffer: &mut Vec<f32>) -> usize {
+        let mut read = 0;
+        while read < frames {
+            if self.position == self.cycle.len() {
+                self.next_cycle();
+            }
+            let remaining = &self.cycle[self.position..];
+            let len = remaining.len().min((frames - read) * self.channels);
+            buffer.extend_from_slice(&remaining[..len]);
+            self.position += len;
+            read += len / self.channels;
+        }
+        read
+    }
+
+    fn is_live(&self) -> bool {
+        false
+    }
+
+    fn name(&self) -> String {
+        "a synthetic test pattern".to_string()
+    }
+}
+
+// Colour gradients with diagonal stripes, shifted along by `shift`
+fn test_pattern(width: u32, height: u32, shift: usize) -> DynamicImage {
+    let img = RgbImage::from_fn(width, height, |x, y| {
+        let stripe = (x + y + shift as u32 * 8) / 16 % 2;
+        Rgb([
+            (x * 255 / width.max(1)) as u8,
+            (y * 255 / height.max(1)) as u8,
+            stripe as u8 * 255,
+        ])
+    });
+    DynamicImage::ImageRgb8(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::drift;
+    use super::super::fft::{self, Detectors};
+    use super::super::recorder::{Capture, CaptureState};
+    use super::super::source::Feeder;
+    use super::*;
+    use codec::Mode;
+    use ringbuf::Rb;
+    use std::time::Duration;
+
+    #[test]
+    fn captures_the_image_between_the_signals() {
+        let profile = Profile {
+            mode: Mode::Luminance,
+            width: 200,
+            height: 100,
+            ..Profile::default()
+        };
+        let sample_rate = profile.detector.sample_rate as u32;
+        let frames = (profile.width * profile.height) as usize;
+        let start_len = profile.detector.start_signal.len(sample_rate);
+        let end_len = profile.detector.end_signal.len(sample_rate);
+        let channels = profile.channels();
+        let expected = codec::encode(
+            &test_pattern(profile.width, profile.height, 0),
+            profile.mode,
+        );
+
+        let mut feeder = Feeder::new(Box::new(Synthetic::new(&profile)), 1.0);
+        let mut capture = Capture::new(channels, frames, fft::MONITOR_LEN, sample_rate, None);
+        let mut detectors = Detectors::new(&profile.detector);
+        // What the renderer would have uploaded so far
+        let mut uploaded: Vec<f32> = Vec::new();
+        let mut states = vec![capture.state];
+
+        // Steps a power of two fraction of a second long, so the frames fed are exact
+        let step = Duration::from_secs(1) / 64;
+        let mut elapsed = Duration::ZERO;
+        let stop = start_len + frames + end_len + sample_rate as usize / 5;
+        loop {
+            elapsed += step;
+            let fed = (elapsed.as_secs_f64() * sample_rate as f64) as usize;
+            if fed > stop {
+                break;
+            }
+            feeder.feed_at(elapsed, &mut capture);
+            fft::update(&mut detectors, &mut capture);
+            if states.last() != Some(&capture.state) {
+                states.push(capture.state);
+            }
+
+            let (offset, samples) = capture.take_new_samples();
+            assert!(offset <= uploaded.len());
+            uploaded.truncate(offset);
+            uploaded.extend_from_slice(samples);
+            assert_eq!(uploaded.len(), capture.write_cursor());
+            assert!(uploaded.iter().eq(capture.image.iter()));
+
+            // Once the end of the start signal is found the image starts on the exact sample
+            // after it, behind by what the resampler holds back
+            let into_image = fed.saturating_sub(start_len + drift::HALF_TAPS);
+            if capture.state == CaptureState::Recording && into_image > sample_rate as usize / 10 {
+                assert_eq!(capture.write_cursor(), into_image * channels);
+            }
+        }
+
+        assert_eq!(
+            states,
+            [
+                CaptureState::FreeRunning,
+                CaptureState::Recording,
+                CaptureState::Complete
+            ]
+        );
+        assert_eq!(uploaded, expected);
+        let completed = capture.take_completed().unwrap();
+        assert_eq!(completed.number, 1);
+        assert_eq!(completed.samples, expected);
+    }
+}
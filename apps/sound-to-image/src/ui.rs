@@ -1,42 +1,326 @@
+use super::fft::DetectorConfig;
+use super::gallery::Gallery;
+use super::profile::{Profile, ProfileFile};
+use super::recorder::{self, InputConfig};
 use super::Model;
 use nannou::prelude::*;
 use nannou_egui::{self, egui, Egui};
+use serde::{Deserialize, Serialize};
 
 pub struct AppUi {
     pub egui: Egui,
     pub settings: Settings,
+    // What the app was launched with, saved along with the settings
+    pub profile: Profile,
+    pub profiles: ProfileFile,
+    pub preset_name: String,
+    input_devices: Vec<String>,
+    // Whether images come from the input device, files and the network can't be switched from here
+    live_input: bool,
 }
+
+// What the panel changed that needs more than new uniforms
+pub enum Change {
+    // The detectors have to be set up again
+    Detector(DetectorConfig),
+    // The input stream has to be opened again, `AppUi::profile` is left to be updated once it is
+    Input(InputConfig),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Settings {
-    pub resolution: f32,
-    pub amp: f32,
+    pub contrast: f32,
+    pub gain: f32,
+    pub offset: f32,
+    pub colour_map: ColourMap,
+    // Draw the image as it comes in, with nothing where no audio arrived yet
+    pub reveal: bool,
+    // Mark where the image is being written while revealing it
+    pub scan_head: bool,
+    // Seconds a completed image stays on screen before the next one can take its place
+    pub hold: f32,
+    // Seconds the held image takes to fade into the next one
+    pub crossfade: f32,
 }
 
-pub fn create_ui(window: &nannou::prelude::Window) -> AppUi {
+// How luminance values are coloured in, rgb images are always shown as they are
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColourMap {
+    Grey,
+    Inverted,
+    Heat,
+    Viridis,
+}
+
+impl ColourMap {
+    const ALL: [ColourMap; 4] = [
+        ColourMap::Grey,
+        ColourMap::Inverted,
+        ColourMap::Heat,
+        ColourMap::Viridis,
+    ];
+
+    // What the shaders expect in `Uniforms::colour_map`
+    pub fn index(&self) -> u32 {
+        match self {
+            ColourMap::Grey => 0,
+            ColourMap::Inverted => 1,
+            ColourMap::Heat => 2,
+            ColourMap::Viridis => 3,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ColourMap::Grey => "Grey",
+            ColourMap::Inverted => "Inverted",
+            ColourMap::Heat => "Heat",
+            ColourMap::Viridis => "Viridis",
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        create_initial_settings()
+    }
+}
+
+pub fn create_ui(
+    window: &nannou::prelude::Window,
+    profile: Profile,
+    profiles: ProfileFile,
+    preset_name: String,
+    live_input: bool,
+) -> AppUi {
     let egui = Egui::from_window(window);
 
-    let settings = create_initial_settings();
+    let settings = profile.shader.clone();
+    let input_devices = recorder::input_device_names();
 
-    return AppUi { egui, settings };
+    return AppUi {
+        egui,
+        settings,
+        profile,
+        profiles,
+        preset_name,
+        input_devices,
+        live_input,
+    };
 }
 
-pub fn update_settings_ui(app_ui: &mut AppUi) {
-    let egui = &mut app_ui.egui;
-    let settings = &mut app_ui.settings;
+pub fn update_settings_ui(app_ui: &mut AppUi, gallery: &mut Gallery) -> Vec<Change> {
+    let AppUi {
+        egui,
+        settings,
+        profile,
+        profiles,
+        preset_name,
+        input_devices,
+        live_input,
+    } = app_ui;
+    let mut picked = None;
+    let mut changes = Vec::new();
 
     let ctx = egui.begin_frame();
     egui::Window::new("Settings").show(&ctx, |ui| {
-        ui.label("Resolution:");
-        if ui
-            .add(egui::Slider::new(&mut settings.resolution, 0.0001..=0.0005))
-            .changed()
-        {}
-
-        ui.label("Amp:");
-        if ui
-            .add(egui::Slider::new(&mut settings.amp, 1.0..=10.0))
-            .changed()
-        {}
+        ui.label("Contrast:");
+        ui.add(egui::Slider::new(&mut settings.contrast, 0.1..=5.0));
+
+        ui.label("Gain:");
+        ui.add(egui::Slider::new(&mut settings.gain, 0.1..=10.0).logarithmic(true));
+
+        ui.label("Offset:");
+        ui.add(egui::Slider::new(&mut settings.offset, -0.5..=0.5));
+
+        egui::ComboBox::from_label("Colour map")
+            .selected_text(settings.colour_map.name())
+            .show_ui(ui, |ui| {
+                for colour_map in ColourMap::ALL {
+                    ui.selectable_value(&mut settings.colour_map, colour_map, colour_map.name());
+                }
+            });
+
+        ui.checkbox(&mut settings.reveal, "Reveal as it arrives");
+        ui.add_enabled(
+            settings.reveal,
+            egui::Checkbox::new(&mut settings.scan_head, "Scan head"),
+        );
+
+        ui.label("Hold (s):");
+        ui.add(egui::Slider::new(&mut settings.hold, 0.0..=60.0));
+
+        ui.label("Crossfade (s):");
+        ui.add(egui::Slider::new(&mut settings.crossfade, 0.0..=10.0));
+
+        ui.separator();
+        ui.label(format!(
+            "Gallery: {} of the last {} images",
+            gallery.len(),
+            gallery.layers()
+        ));
+        ui.horizontal(|ui| {
+            if ui.button("Older").clicked() {
+                gallery.older();
+            }
+            if ui.button("Newer").clicked() {
+                gallery.newer();
+            }
+            if ui.button("Live").clicked() {
+                gallery.shown = None;
+            }
+            ui.checkbox(&mut gallery.grid, "Grid");
+        });
+        ui.label(match gallery.shown {
+            Some(0) => "Showing the last image".to_string(),
+            Some(back) => format!("Showing the image {} before the last", back),
+            None => "Showing the live image".to_string(),
+        });
+
+        ui.separator();
+        let mut picked_input = None;
+        ui.add_enabled_ui(*live_input, |ui| {
+            let current_device = profile.input.device.as_deref().unwrap_or("Default");
+            let mut picked_device = None;
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_label("Input")
+                    .selected_text(current_device)
+                    .show_ui(ui, |ui| {
+                        if ui
+                            .selectable_label(profile.input.device.is_none(), "Default")
+                            .clicked()
+                        {
+                            picked_device = Some(None);
+                        }
+                        for name in input_devices.iter() {
+                            if ui
+                                .selectable_label(name.as_str() == current_device, name)
+                                .clicked()
+                            {
+                                picked_device = Some(Some(name.clone()));
+                            }
+                        }
+                    });
+                if ui.button("Refresh").clicked() {
+                    *input_devices = recorder::input_device_names();
+                }
+            });
+            picked_input = picked_device.map(|device| InputConfig {
+                device,
+                ..profile.input.clone()
+            });
+            ui.horizontal(|ui| {
+                let picked =
+                    option_combo(ui, "Sample rate", profile.input.sample_rate, &SAMPLE_RATES);
+                if let Some(sample_rate) = picked {
+                    picked_input = Some(InputConfig {
+                        sample_rate,
+                        ..profile.input.clone()
+                    });
+                }
+                let picked =
+                    option_combo(ui, "Buffer size", profile.input.buffer_size, &BUFFER_SIZES);
+                if let Some(buffer_size) = picked {
+                    picked_input = Some(InputConfig {
+                        buffer_size,
+                        ..profile.input.clone()
+                    });
+                }
+            });
+        });
+        if let Some(input) = picked_input {
+            if input != profile.input {
+                changes.push(Change::Input(input));
+            }
+        }
+
+        ui.separator();
+        ui.label(format!("Profile: {}", profiles.path));
+        egui::ComboBox::from_label("Preset")
+            .selected_text(preset_name.as_str())
+            .show_ui(ui, |ui| {
+                for name in profiles.names() {
+                    if ui
+                        .selectable_label(name.as_str() == preset_name.as_str(), name)
+                        .clicked()
+                    {
+                        picked = Some(name.clone());
+                    }
+                }
+            });
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(preset_name);
+            if ui.button("Save").clicked() && !preset_name.is_empty() {
+                let saved = Profile {
+                    shader: settings.clone(),
+                    ..profile.clone()
+                };
+                match profiles.save(preset_name, saved) {
+                    Ok(()) => println!("Saved preset {:?} to {}", preset_name, profiles.path),
+                    Err(e) => println!("Failed to save profile to {}: {}", profiles.path, e),
+                }
+            }
+        });
     });
+
+    let picked = picked.and_then(|name| Some((profiles.get(&name)?.clone(), name)));
+    if let Some((preset, name)) = picked {
+        if profile.needs_restart(&preset) {
+            println!(
+                "Preset {:?} changes how images are decoded, restart with --preset {} for that",
+                name, name
+            );
+        }
+        *settings = preset.shader;
+        match preset.detector.validate() {
+            Ok(()) => {
+                profile.detector = preset.detector.clone();
+                changes.push(Change::Detector(preset.detector));
+            }
+            Err(e) => println!("Keeping the current detector, preset {:?}: {}", name, e),
+        }
+        if !*live_input {
+            // Nothing to reopen, only kept for when the preset is saved
+            profile.input = preset.input;
+        } else if preset.input != profile.input {
+            changes.push(Change::Input(preset.input));
+        }
+        *preset_name = name;
+    }
+    changes
+}
+
+// Offered for the input device, anything else can still be set in a preset or on the command line
+const SAMPLE_RATES: [u32; 6] = [44100, 48000, 88200, 96000, 176400, 192000];
+const BUFFER_SIZES: [usize; 7] = [64, 128, 256, 512, 1024, 2048, 4096];
+
+// Picks one of `options` or the device's default. Returns what was picked, if anything was
+fn option_combo<T: Copy + PartialEq + ToString>(
+    ui: &mut egui::Ui,
+    label: &str,
+    current: Option<T>,
+    options: &[T],
+) -> Option<Option<T>> {
+    let mut picked = None;
+    let text = current.map_or("Default".to_string(), |value| value.to_string());
+    egui::ComboBox::from_label(label)
+        .selected_text(text)
+        .show_ui(ui, |ui| {
+            if ui.selectable_label(current.is_none(), "Default").clicked() {
+                picked = Some(None);
+            }
+            for option in options {
+                if ui
+                    .selectable_label(current == Some(*option), option.to_string())
+                    .clicked()
+                {
+                    picked = Some(Some(*option));
+                }
+            }
+        });
+    picked
 }
 
 pub fn show(model: &Model, frame: &Frame) {
@@ -45,8 +329,14 @@ pub fn show(model: &Model, frame: &Frame) {
 
 fn create_initial_settings() -> Settings {
     let settings = Settings {
-        resolution: 0.00046,
-        amp: 1.0,
+        contrast: 2.2,
+        gain: 1.0,
+        offset: 0.0,
+        colour_map: ColourMap::Grey,
+        reveal: false,
+        scan_head: true,
+        hold: 5.0,
+        crossfade: 1.0,
     };
     settings
 }
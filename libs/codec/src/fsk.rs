@@ -0,0 +1,357 @@
+// The robust alternative to the raw encoding. Pixel values are quantised to 4 bits, packed into
+// Reed-Solomon blocks and sent as multi-tone FSK: every symbol plays one of 16 tones in each of
+// four groups, so it carries four 4 bit values. Deciding which tone is loudest doesn't care about
+// the overall gain, the parity bytes fix what noise gets wrong, and a guard interval before
+// every symbol lets the room's echo of the previous one die down.
+use super::reed_solomon::{self, BLOCK_LEN, DATA_LEN};
+use super::{brightness_to_sample, Mode};
+use std::f32::consts::PI;
+
+// Samples the demodulator looks at per symbol, tones sit on whole multiples of
+// SAMPLE_RATE / SYMBOL_LEN so they don't leak into each other
+const SYMBOL_LEN: usize = 512;
+// Extra samples every tone is held for before the part that is looked at
+const GUARD_LEN: usize = 128;
+const SYMBOL_STRIDE: usize = SYMBOL_LEN + GUARD_LEN;
+
+const GROUPS: usize = 4;
+const TONES: usize = 16;
+// Pixel values are quantised to this many levels, tone mapping can dither to them beforehand
+pub const LEVELS: u32 = TONES as u32;
+const BITS: u32 = 4;
+// About 1.5 kHz to 12.5 kHz. The start and end signals both have a tone further down, so a
+// symbol can't be mistaken for them
+const FIRST_BIN: usize = 18;
+
+// Peak amplitude of all groups together
+const LEVEL: f32 = 0.8;
+
+// Reed-Solomon blocks are sent byte by byte in turn, so a burst of noise spreads over several
+// blocks instead of overwhelming one
+const INTERLEAVE: usize = 8;
+
+// How far either side of the window the timing tracker looks, and how much disagreement it
+// collects before moving the window by a sample
+const TRACK_OFFSET: usize = GUARD_LEN / 2 + GUARD_LEN / 8;
+const TRACK_THRESHOLD: f32 = 1.0;
+
+// Tones of a group are spread over the whole band, so a dip in the room's response costs every
+// group a tone rather than one group all of them. Every other symbol uses the bins in between,
+// so the echo of one symbol doesn't land on the tones of the next
+fn bin(symbol: usize, group: usize, tone: usize) -> usize {
+    FIRST_BIN + (tone * GROUPS + group) * 2 + symbol % 2
+}
+
+fn values(width: u32, height: u32, mode: Mode) -> usize {
+    (width * height) as usize * mode.channels() as usize
+}
+
+fn blocks(width: u32, height: u32, mode: Mode) -> usize {
+    blocks_for(values(width, height, mode))
+}
+
+fn blocks_for(values: usize) -> usize {
+    values.div_ceil(2).div_ceil(DATA_LEN)
+}
+
+fn symbols(blocks: usize) -> usize {
+    (blocks * BLOCK_LEN).div_ceil(GROUPS * BITS as usize / 8)
+}
+
+// Samples the modulated image takes up
+pub fn len(width: u32, height: u32, mode: Mode) -> usize {
+    symbols(blocks(width, height, mode)) * SYMBOL_STRIDE
+}
+
+// Takes the values of every pixel like `pixel_values` returns them
+pub fn modulate(values: &[u8]) -> Vec<f32> {
+    let quantised: Vec<u8> = values.iter().map(|value| quantise(*value)).collect();
+    let bytes: Vec<u8> = quantised
+        .chunks(2)
+        .map(|pair| pair[0] << BITS | pair.get(1).copied().unwrap_or(0))
+        .collect();
+
+    let blocks: Vec<[u8; BLOCK_LEN]> = bytes.chunks(DATA_LEN).map(reed_solomon::encode).collect();
+    let stream: Vec<u8> = blocks.chunks(INTERLEAVE).flat_map(interleave).collect();
+
+    let mut samples = Vec::with_capacity(symbols(blocks_for(values.len())) * SYMBOL_STRIDE);
+    let nibbles = |byte: &u8| [byte >> BITS, byte & 0xf];
+    let tones: Vec<u8> = stream.iter().flat_map(nibbles).collect();
+    for (symbol, symbol_tones) in tones.chunks(GROUPS).enumerate() {
+        samples.extend((0..SYMBOL_STRIDE).map(|n| {
+            let tones: f32 = symbol_tones
+                .iter()
+                .enumerate()
+                .map(|(group, tone)| {
+                    let bin = bin(symbol, group, *tone as usize) as f32;
+                    (2.0 * PI * bin * n as f32 / SYMBOL_LEN as f32).sin()
+                })
+                .sum();
+            tones * LEVEL / GROUPS as f32
+        }));
+    }
+    samples
+}
+
+fn quantise(brightness: u8) -> u8 {
+    ((brightness as u32 * (TONES as u32 - 1) + 127) / 255) as u8
+}
+
+fn dequantise(value: u8) -> f32 {
+    brightness_to_sample(value * (255 / (TONES as u8 - 1)))
+}
+
+fn interleave(blocks: &[[u8; BLOCK_LEN]]) -> Vec<u8> {
+    (0..BLOCK_LEN)
+        .flat_map(|i| blocks.iter().map(move |block| block[i]))
+        .collect()
+}
+
+fn deinterleave(bytes: &[u8], blocks: usize) -> Vec<[u8; BLOCK_LEN]> {
+    let mut deinterleaved = vec![[0; BLOCK_LEN]; blocks];
+    for (i, byte) in bytes.iter().enumerate() {
+        deinterleaved[i % blocks][i / blocks] = *byte;
+    }
+    deinterleaved
+}
+
+// Turns the audio after the start signal back into samples like `encode` makes, a sample at a
+// time so it can sit in the live capture. Values come out a group of interleaved blocks at a time
+pub struct Demodulator {
+    values: usize,
+    blocks: usize,
+    // Audio not needed by any symbol anymore is dropped from the front
+    audio: Vec<f32>,
+    dropped: usize,
+    symbol: usize,
+    // Samples the symbols turned out to be late by, following the clock of whatever played them
+    timing: isize,
+    timing_error: f32,
+    // Bytes of the blocks still being received
+    received: Vec<u8>,
+    blocks_done: usize,
+    values_done: usize,
+    failed_blocks: usize,
+}
+
+impl Demodulator {
+    pub fn new(width: u32, height: u32, mode: Mode) -> Self {
+        Demodulator {
+            values: values(width, height, mode),
+            blocks: blocks(width, height, mode),
+            audio: Vec::new(),
+            dropped: 0,
+            symbol: 0,
+            timing: 0,
+            timing_error: 0.0,
+            received: Vec::new(),
+            blocks_done: 0,
+            values_done: 0,
+            failed_blocks: 0,
+        }
+    }
+
+    // The next sample pushed is where the first symbol begins
+    pub fn reset(&mut self) {
+        self.audio.clear();
+        self.dropped = 0;
+        self.symbol = 0;
+        self.timing = 0;
+        self.timing_error = 0.0;
+        self.received.clear();
+        self.blocks_done = 0;
+        self.values_done = 0;
+        self.failed_blocks = 0;
+    }
+
+    // Samples the whole image takes up
+    pub fn stream_len(&self) -> usize {
+        symbols(self.blocks) * SYMBOL_STRIDE
+    }
+
+    // Blocks with more errors than the parity could fix so far, their values are used as they came
+    pub fn failed_blocks(&self) -> usize {
+        self.failed_blocks
+    }
+
+    pub fn push(&mut self, sample: f32, output: &mut Vec<f32>) {
+        if self.blocks_done == self.blocks {
+            return;
+        }
+        self.audio.push(sample);
+
+        // Centre of the guard interval when the clocks agree
+        let start = (self.symbol * SYMBOL_STRIDE + GUARD_LEN / 2) as isize + self.timing
+            - self.dropped as isize;
+        // Nothing comes after the last symbol to track the timing against, so it mustn't wait
+        // for samples past the end of the stream
+        let last = self.symbol + 1 == symbols(self.blocks);
+        let lookahead = if last { 0 } else { TRACK_OFFSET };
+        let end = start + (lookahead + SYMBOL_LEN) as isize;
+        if start < 0 || end > self.audio.len() as isize {
+            return;
+        }
+        let start = start as usize;
+
+        let powers = self.powers(start);
+        for pair in powers.chunks(2) {
+            let [high, low] = [&pair[0], &pair[1]].map(loudest);
+            self.received.push(high << BITS | low);
+        }
+        if !last {
+            self.track(start);
+        }
+        self.symbol += 1;
+
+        let unused = start.saturating_sub(TRACK_OFFSET);
+        self.audio.drain(..unused);
+        self.dropped += unused;
+
+        self.decode_blocks(output);
+    }
+
+    // Power of every group's tones in the window starting at `start`
+    fn powers(&self, start: usize) -> Vec<[f32; TONES]> {
+        let window = &self.audio[start..start + SYMBOL_LEN];
+        (0..GROUPS)
+            .map(|group| {
+                std::array::from_fn(|tone| goertzel(window, bin(self.symbol, group, tone)))
+            })
+            .collect()
+    }
+
+    // Windows straddling two symbols smear the tones, so the one closer to the edge of its
+    // symbol looks less clean than the other. Nudges the timing towards the cleaner side
+    fn track(&mut self, start: usize) {
+        if start < TRACK_OFFSET {
+            return;
+        }
+        let clarity = |start: usize| {
+            self.powers(start)
+                .iter()
+                .map(|group| {
+                    let total: f32 = group.iter().sum();
+                    group.iter().copied().fold(0.0, f32::max) / total.max(f32::MIN_POSITIVE)
+                })
+                .sum::<f32>()
+                / GROUPS as f32
+        };
+        self.timing_error += clarity(start + TRACK_OFFSET) - clarity(start - TRACK_OFFSET);
+        if self.timing_error > TRACK_THRESHOLD {
+            self.timing += 1;
+            self.timing_error = 0.0;
+        } else if self.timing_error < -TRACK_THRESHOLD {
+            self.timing -= 1;
+            self.timing_error = 0.0;
+        }
+    }
+
+    fn decode_blocks(&mut self, output: &mut Vec<f32>) {
+        let blocks = INTERLEAVE.min(self.blocks - self.blocks_done);
+        if self.received.len() < blocks * BLOCK_LEN {
+            return;
+        }
+        let bytes: Vec<u8> = self.received.drain(..blocks * BLOCK_LEN).collect();
+        for mut block in deinterleave(&bytes, blocks) {
+            if reed_solomon::decode(&mut block).is_none() {
+                self.failed_blocks += 1;
+            }
+            for byte in &block[..DATA_LEN] {
+                for value in [byte >> BITS, byte & 0xf] {
+                    if self.values_done < self.values {
+                        output.push(dequantise(value));
+                        self.values_done += 1;
+                    }
+                }
+            }
+        }
+        self.blocks_done += blocks;
+    }
+}
+
+fn loudest(powers: &[f32; TONES]) -> u8 {
+    (0..TONES)
+        .max_by(|a, b| powers[*a].total_cmp(&powers[*b]))
+        .unwrap_or(0) as u8
+}
+
+// Power of a single DFT bin
+fn goertzel(window: &[f32], bin: usize) -> f32 {
+    let coefficient = 2.0 * (2.0 * PI * bin as f32 / window.len() as f32).cos();
+    let (s1, s2) = window
+        .iter()
+        .fold((0.0, 0.0), |(s1, s2), x| (x + coefficient * s1 - s2, s1));
+    s1 * s1 + s2 * s2 - coefficient * s1 * s2
+}
+
+// Demodulates a whole recording, `samples` starting with the first symbol. Also returns how many
+// blocks couldn't be corrected
+pub fn demodulate(samples: &[f32], width: u32, height: u32, mode: Mode) -> (Vec<f32>, usize) {
+    let mut demodulator = Demodulator::new(width, height, mode);
+    let mut decoded = Vec::with_capacity(values(width, height, mode));
+    for sample in samples {
+        demodulator.push(*sample, &mut decoded);
+    }
+    (decoded, demodulator.failed_blocks())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample_to_brightness;
+
+    const WIDTH: u32 = 37;
+    const HEIGHT: u32 = 11;
+
+    fn pixel_values() -> Vec<u8> {
+        (0..values(WIDTH, HEIGHT, Mode::Rgb))
+            .map(|i| (i * 97 % 256) as u8)
+            .collect()
+    }
+
+    // What the values should come back as, quantised to LEVELS
+    fn expected(values: &[u8]) -> Vec<u8> {
+        values
+            .iter()
+            .map(|value| sample_to_brightness(dequantise(quantise(*value))))
+            .collect()
+    }
+
+    fn brightness(samples: &[f32]) -> Vec<u8> {
+        samples
+            .iter()
+            .map(|sample| sample_to_brightness(*sample))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_a_stream_of_exactly_len_samples() {
+        let values = pixel_values();
+        let samples = modulate(&values);
+        assert_eq!(samples.len(), len(WIDTH, HEIGHT, Mode::Rgb));
+        let (decoded, failed_blocks) = demodulate(&samples, WIDTH, HEIGHT, Mode::Rgb);
+        assert_eq!(failed_blocks, 0);
+        assert_eq!(brightness(&decoded), expected(&values));
+    }
+
+    #[test]
+    fn survives_gain_noise_and_offset() {
+        let values = pixel_values();
+        // Deterministic noise from a xorshift generator, about -0.05 to 0.05
+        let mut state: u32 = 0x2545_f491;
+        let mut noise = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state as f32 / u32::MAX as f32 - 0.5) * 0.1
+        };
+        // Starts 20 samples late
+        let mut samples = vec![0.0; 20];
+        samples.extend(modulate(&values).iter().map(|sample| sample * 0.3));
+        let samples: Vec<f32> = samples.iter().map(|sample| sample + noise()).collect();
+
+        let (decoded, failed_blocks) = demodulate(&samples, WIDTH, HEIGHT, Mode::Rgb);
+        assert_eq!(failed_blocks, 0);
+        assert_eq!(brightness(&decoded), expected(&values));
+    }
+}
@@ -0,0 +1,125 @@
+// The mapping between pixels and audio samples shared by image-to-sound and sound-to-image.
+// Pixels are read row-major, one audio frame per pixel, each channel value 0..=255 spread
+// linearly over -1.0..=1.0. The fragment shader in sound-to-image does the inverse on the GPU, so any change
+// here has to be mirrored in `apps/sound-to-image/src/shaders/fs.wgsl`.
+use image::{DynamicImage, GenericImageView, GrayImage, Luma, Rgb, RgbImage};
+
+mod mode;
+pub use mode::Mode;
+
+mod modulation;
+pub use modulation::Modulation;
+
+pub mod metadata;
+pub use metadata::Metadata;
+
+pub mod signal;
+pub use signal::ToneBurst;
+
+pub mod sync;
+
+pub mod fsk;
+pub mod reed_solomon;
+
+pub mod tone;
+pub use tone::{ToneCurve, ToneMap};
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+pub const DEFAULT_WIDTH: u32 = 500;
+pub const DEFAULT_HEIGHT: u32 = 500;
+
+// Returns interleaved samples, `mode.channels()` per pixel
+pub fn encode(img: &DynamicImage, mode: Mode) -> Vec<f32> {
+    encode_values(&pixel_values(img, mode))
+}
+
+// The values `encode` turns into samples, `mode.channels()` per pixel
+pub fn pixel_values(img: &DynamicImage, mode: Mode) -> Vec<u8> {
+    let mut values =
+        Vec::with_capacity((img.width() * img.height()) as usize * mode.channels() as usize);
+    for (_, _, pixel) in img.pixels() {
+        let rgba = pixel.0;
+        match mode {
+            Mode::Luminance => {
+                let brightness = (rgba[0] as u32 + rgba[1] as u32 + rgba[2] as u32) / 3;
+                values.push(brightness as u8);
+            }
+            Mode::Rgb => values.extend_from_slice(&rgba[..3]),
+        }
+    }
+    values
+}
+
+pub fn encode_values(values: &[u8]) -> Vec<f32> {
+    values
+        .iter()
+        .map(|value| brightness_to_sample(*value))
+        .collect()
+}
+
+pub fn decode(samples: &[f32], width: u32, height: u32, mode: Mode) -> DynamicImage {
+    let channels = mode.channels() as usize;
+    // Missing samples read as 0.0, just like the zero-initialised storage buffer on the GPU
+    let brightness_at = |x: u32, y: u32, channel: usize| {
+        let index = (y * width + x) as usize * channels + channel;
+        sample_to_brightness(samples.get(index).copied().unwrap_or(0.0))
+    };
+    match mode {
+        Mode::Luminance => DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+            Luma([brightness_at(x, y, 0)])
+        })),
+        Mode::Rgb => DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            Rgb([
+                brightness_at(x, y, 0),
+                brightness_at(x, y, 1),
+                brightness_at(x, y, 2),
+            ])
+        })),
+    }
+}
+
+pub fn brightness_to_sample(brightness: u8) -> f32 {
+    (brightness as f32 / 255.0) * 2.0 - 1.0
+}
+
+pub fn sample_to_brightness(sample: f32) -> u8 {
+    // Same as `(sampleValue + 1.0) * 0.5` in fs.wgsl, before any contrast adjustment
+    let value = (sample + 1.0) * 0.5;
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+pub fn sample_to_i16(sample: f32) -> i16 {
+    (sample * i16::MAX as f32) as i16
+}
+
+pub fn sample_from_i16(sample: i16) -> f32 {
+    sample as f32 / i16::MAX as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every value survives being written to a 16-bit WAV and read back
+    fn round_trip(img: DynamicImage, mode: Mode) {
+        let samples: Vec<f32> = encode(&img, mode)
+            .into_iter()
+            .map(|sample| sample_from_i16(sample_to_i16(sample)))
+            .collect();
+        let decoded = decode(&samples, img.width(), img.height(), mode);
+        assert_eq!(pixel_values(&decoded, mode), pixel_values(&img, mode));
+    }
+
+    #[test]
+    fn luminance_round_trip() {
+        let img = GrayImage::from_fn(256, 1, |x, _| Luma([x as u8]));
+        round_trip(DynamicImage::ImageLuma8(img), Mode::Luminance);
+    }
+
+    #[test]
+    fn rgb_round_trip() {
+        let img = RgbImage::from_fn(256, 1, |x, _| Rgb([x as u8, 255 - x as u8, x as u8 / 2]));
+        round_trip(DynamicImage::ImageRgb8(img), Mode::Rgb);
+    }
+}
@@ -0,0 +1,251 @@
+// Describes the encoded image. It is stored in a custom RIFF chunk after the audio data, where
+// WAV readers that don't know about it (hound included) simply skip it. The payload is plain
+// `key=value` lines so fields can be added without breaking older files.
+use super::{Mode, Modulation, ToneCurve, DEFAULT_HEIGHT, DEFAULT_WIDTH};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+// Goes up with every key that changes how the samples are laid out, so readers that would skip
+// it refuse the file instead of decoding garbage:
+// 2: row_sync, 3: modulation, 4: tone_curve
+pub const VERSION: u32 = 4;
+
+const CHUNK_ID: &[u8; 4] = b"eamd";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub version: u32,
+    pub width: u32,
+    pub height: u32,
+    pub mode: Mode,
+    // Whether every row starts with a `sync::MARKER`
+    pub row_sync: bool,
+    pub modulation: Modulation,
+    // The curve the values went through before encoding, if any
+    pub tone_curve: Option<ToneCurve>,
+}
+
+impl Metadata {
+    pub fn new(width: u32, height: u32, mode: Mode) -> Self {
+        Metadata {
+            version: VERSION,
+            width,
+            height,
+            mode,
+            row_sync: false,
+            modulation: Modulation::Raw,
+            tone_curve: None,
+        }
+    }
+
+    // FSK is always mono, whatever the pixels hold
+    pub fn channels(&self) -> u16 {
+        match self.modulation {
+            Modulation::Raw => self.mode.channels(),
+            Modulation::Fsk => 1,
+        }
+    }
+}
+
+impl Default for Metadata {
+    fn default() -> Self {
+        Metadata::new(DEFAULT_WIDTH, DEFAULT_HEIGHT, Mode::default())
+    }
+}
+
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "version={}", self.version)?;
+        writeln!(f, "width={}", self.width)?;
+        writeln!(f, "height={}", self.height)?;
+        writeln!(f, "mode={}", self.mode)?;
+        writeln!(f, "row_sync={}", self.row_sync)?;
+        writeln!(f, "modulation={}", self.modulation)?;
+        if let Some(tone_curve) = &self.tone_curve {
+            writeln!(f, "tone_curve={}", tone_curve)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Metadata {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut metadata = Metadata::default();
+        for line in s.lines() {
+            // Unknown keys come from newer versions, skip them
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let parse_u32 = |value: &str| {
+                value
+                    .parse::<u32>()
+                    .map_err(|e| format!("invalid {}: {}", key, e))
+            };
+            match key {
+                "version" => metadata.version = parse_u32(value)?,
+                "width" => metadata.width = parse_u32(value)?,
+                "height" => metadata.height = parse_u32(value)?,
+                "mode" => metadata.mode = value.parse()?,
+                "row_sync" => {
+                    metadata.row_sync = value
+                        .parse()
+                        .map_err(|e| format!("invalid {}: {}", key, e))?
+                }
+                "modulation" => metadata.modulation = value.parse()?,
+                "tone_curve" => metadata.tone_curve = Some(value.parse()?),
+                _ => {}
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+// Appends the metadata chunk to a finalized WAV file and fixes up the RIFF size
+pub fn write(path: impl AsRef<Path>, metadata: &Metadata) -> io::Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+
+    let mut payload = metadata.to_string().into_bytes();
+    let chunk_len = payload.len() as u32;
+    // RIFF chunks are padded to an even length
+    if payload.len() % 2 == 1 {
+        payload.push(0);
+    }
+
+    let mut riff_len = [0u8; 4];
+    file.seek(SeekFrom::Start(4))?;
+    file.read_exact(&mut riff_len)?;
+    let riff_len = u32::from_le_bytes(riff_len) + 8 + payload.len() as u32;
+
+    file.seek(SeekFrom::End(0))?;
+    file.write_all(CHUNK_ID)?;
+    file.write_all(&chunk_len.to_le_bytes())?;
+    file.write_all(&payload)?;
+
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&riff_len.to_le_bytes())?;
+    Ok(())
+}
+
+// Returns None for files written before metadata existed, and an error for files written by a
+// newer, incompatible version
+pub fn read(path: impl AsRef<Path>) -> io::Result<Option<Metadata>> {
+    let bytes = fs::read(path)?;
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a WAV file"));
+    }
+
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let len = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        let start = offset + 8;
+        let end = (start + len).min(bytes.len());
+        if id == CHUNK_ID {
+            let payload = String::from_utf8_lossy(&bytes[start..end]);
+            let metadata: Metadata = payload
+                .trim_end_matches('\0')
+                .parse()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            // Newer versions may lay the samples out differently, decoding them would be garbage
+            if metadata.version > VERSION {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "written by metadata version {}, only up to {} is supported",
+                        metadata.version, VERSION
+                    ),
+                ));
+            }
+            return Ok(Some(metadata));
+        }
+        offset = start + len + len % 2;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // A mono 16 bit WAV with `frames` silent frames, as hound would finalize it
+    fn write_wav(name: &str, frames: u32) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("metadata_{}.wav", name));
+        let data_len = frames * 2;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&crate::SAMPLE_RATE.to_le_bytes());
+        bytes.extend_from_slice(&(crate::SAMPLE_RATE * 2).to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        bytes.resize(bytes.len() + data_len as usize, 0);
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn round_trip(name: &str, metadata: &Metadata) -> io::Result<Option<Metadata>> {
+        let path = write_wav(name, 10);
+        write(&path, metadata).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        // The RIFF size still covers the whole file, padding included
+        let riff_len = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        assert_eq!(riff_len, bytes.len() - 8);
+        assert_eq!(bytes.len() % 2, 0);
+        let read = read(&path);
+        fs::remove_file(&path).unwrap();
+        read
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let metadata = Metadata {
+            row_sync: true,
+            modulation: Modulation::Fsk,
+            tone_curve: Some(ToneCurve(std::array::from_fn(|value| (value / 2) as u8))),
+            ..Metadata::new(320, 240, Mode::Rgb)
+        };
+        assert_eq!(round_trip("full", &metadata).unwrap(), Some(metadata));
+    }
+
+    #[test]
+    fn round_trip_with_odd_payload() {
+        // One of the two widths makes the payload an odd number of bytes
+        let payload_len = |width| Metadata::new(width, 20, Mode::Luminance).to_string().len();
+        assert_ne!(payload_len(10) % 2, payload_len(100) % 2);
+        for width in [10, 100] {
+            let metadata = Metadata::new(width, 20, Mode::Luminance);
+            let name = format!("odd_{}", width);
+            assert_eq!(round_trip(&name, &metadata).unwrap(), Some(metadata));
+        }
+    }
+
+    #[test]
+    fn wav_without_chunk() {
+        let path = write_wav("plain", 10);
+        let read = read(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let metadata = Metadata {
+            version: VERSION + 1,
+            ..Metadata::default()
+        };
+        let error = round_trip("newer", &metadata).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
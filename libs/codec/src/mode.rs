@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// How pixels are laid out in the audio. Every pixel is one audio frame either way, so an image
+// takes the same time to play back in both modes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    // One channel carrying the average of r, g and b
+    #[default]
+    Luminance,
+    // Three interleaved channels carrying r, g and b
+    Rgb,
+}
+
+impl Mode {
+    pub fn channels(self) -> u16 {
+        match self {
+            Mode::Luminance => 1,
+            Mode::Rgb => 3,
+        }
+    }
+
+    pub fn from_channels(channels: u16) -> Option<Mode> {
+        match channels {
+            1 => Some(Mode::Luminance),
+            3 => Some(Mode::Rgb),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mode::Luminance => write!(f, "luminance"),
+            Mode::Rgb => write!(f, "rgb"),
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "luminance" => Ok(Mode::Luminance),
+            "rgb" => Ok(Mode::Rgb),
+            _ => Err(format!("unknown mode {:?}, expected luminance or rgb", s)),
+        }
+    }
+}
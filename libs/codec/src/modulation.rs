@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// How the pixels are turned into sound, independent of the `Mode` saying which values a pixel has
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Modulation {
+    // One sample per pixel value, see `encode`. Fast, but only survives a clean cable
+    #[default]
+    Raw,
+    // Quantised pixel values protected by Reed-Solomon and sent as multi-tone FSK, see `fsk`.
+    // Much slower, but survives speakers, microphones and the room in between
+    Fsk,
+}
+
+impl fmt::Display for Modulation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Modulation::Raw => write!(f, "raw"),
+            Modulation::Fsk => write!(f, "fsk"),
+        }
+    }
+}
+
+impl FromStr for Modulation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Modulation::Raw),
+            "fsk" => Ok(Modulation::Fsk),
+            _ => Err(format!("unknown modulation {:?}, expected raw or fsk", s)),
+        }
+    }
+}
@@ -0,0 +1,259 @@
+// Reed-Solomon over GF(2^8), the classic RS(255, 223): every block of 223 data bytes gets 32
+// parity bytes and up to 16 wrong bytes per block can be corrected. Polynomials are stored
+// highest degree first.
+
+pub const BLOCK_LEN: usize = 255;
+pub const PARITY_LEN: usize = 32;
+pub const DATA_LEN: usize = BLOCK_LEN - PARITY_LEN;
+
+// x^8 + x^4 + x^3 + x^2 + 1
+const PRIMITIVE: u16 = 0x11d;
+
+struct Field {
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+
+const FIELD: Field = build_field();
+
+const fn build_field() -> Field {
+    let mut exp = [0u8; 512];
+    let mut log = [0u8; 256];
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= PRIMITIVE;
+        }
+        i += 1;
+    }
+    // Doubled so products can index without a modulo
+    while i < 512 {
+        exp[i] = exp[i - 255];
+        i += 1;
+    }
+    Field { exp, log }
+}
+
+fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    FIELD.exp[FIELD.log[a as usize] as usize + FIELD.log[b as usize] as usize]
+}
+
+fn div(a: u8, b: u8) -> u8 {
+    if a == 0 {
+        return 0;
+    }
+    FIELD.exp[(FIELD.log[a as usize] as usize + 255 - FIELD.log[b as usize] as usize) % 255]
+}
+
+// α raised to `power`, which may be negative
+fn alpha_pow(power: i32) -> u8 {
+    FIELD.exp[power.rem_euclid(255) as usize]
+}
+
+fn poly_scale(p: &[u8], x: u8) -> Vec<u8> {
+    p.iter().map(|c| mul(*c, x)).collect()
+}
+
+fn poly_add(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let len = p.len().max(q.len());
+    let mut sum = vec![0; len];
+    for (i, c) in p.iter().enumerate() {
+        sum[i + len - p.len()] = *c;
+    }
+    for (i, c) in q.iter().enumerate() {
+        sum[i + len - q.len()] ^= *c;
+    }
+    sum
+}
+
+fn poly_mul(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let mut product = vec![0; p.len() + q.len() - 1];
+    for (j, b) in q.iter().enumerate() {
+        for (i, a) in p.iter().enumerate() {
+            product[i + j] ^= mul(*a, *b);
+        }
+    }
+    product
+}
+
+fn poly_eval(p: &[u8], x: u8) -> u8 {
+    p.iter().fold(0, |y, c| mul(y, x) ^ c)
+}
+
+fn generator() -> Vec<u8> {
+    (0..PARITY_LEN as i32).fold(vec![1], |g, i| poly_mul(&g, &[1, alpha_pow(i)]))
+}
+
+// Returns the 223 data bytes followed by 32 parity bytes, `data` being padded with zeros if it
+// is shorter
+pub fn encode(data: &[u8]) -> [u8; BLOCK_LEN] {
+    let mut block = [0; BLOCK_LEN];
+    block[..data.len().min(DATA_LEN)].copy_from_slice(&data[..data.len().min(DATA_LEN)]);
+
+    // The parity bytes are the remainder of dividing by the generator polynomial
+    let generator = generator();
+    let mut remainder = block;
+    for i in 0..DATA_LEN {
+        let coefficient = remainder[i];
+        if coefficient != 0 {
+            for (j, g) in generator.iter().enumerate().skip(1) {
+                remainder[i + j] ^= mul(*g, coefficient);
+            }
+        }
+    }
+    block[DATA_LEN..].copy_from_slice(&remainder[DATA_LEN..]);
+    block
+}
+
+// Corrects `block` in place and returns how many bytes were wrong, or None if there were too
+// many errors to correct, in which case the block is left as it was
+pub fn decode(block: &mut [u8; BLOCK_LEN]) -> Option<usize> {
+    let syndromes = syndromes(block);
+    if syndromes.iter().all(|s| *s == 0) {
+        return Some(0);
+    }
+
+    let locator = error_locator(&syndromes)?;
+    let positions = error_positions(&locator)?;
+    let mut corrected = *block;
+    correct_errata(&mut corrected, &syndromes, &positions);
+    if syndromes_of(&corrected).iter().any(|s| *s != 0) {
+        return None;
+    }
+    *block = corrected;
+    Some(positions.len())
+}
+
+fn syndromes_of(block: &[u8]) -> Vec<u8> {
+    (0..PARITY_LEN as i32)
+        .map(|i| poly_eval(block, alpha_pow(i)))
+        .collect()
+}
+
+// With a leading zero, which the error locator search expects
+fn syndromes(block: &[u8]) -> Vec<u8> {
+    let mut syndromes = vec![0];
+    syndromes.extend(syndromes_of(block));
+    syndromes
+}
+
+// Berlekamp-Massey
+fn error_locator(syndromes: &[u8]) -> Option<Vec<u8>> {
+    let mut locator = vec![1];
+    let mut old_locator = vec![1];
+    let shift = syndromes.len() - PARITY_LEN;
+    for i in 0..PARITY_LEN {
+        let k = i + shift;
+        let mut delta = syndromes[k];
+        for j in 1..locator.len() {
+            delta ^= mul(locator[locator.len() - 1 - j], syndromes[k - j]);
+        }
+        old_locator.push(0);
+        if delta != 0 {
+            if old_locator.len() > locator.len() {
+                let new_locator = poly_scale(&old_locator, delta);
+                old_locator = poly_scale(&locator, div(1, delta));
+                locator = new_locator;
+            }
+            locator = poly_add(&locator, &poly_scale(&old_locator, delta));
+        }
+    }
+
+    let leading_zeros = locator.iter().take_while(|c| **c == 0).count();
+    let locator = locator[leading_zeros..].to_vec();
+    if (locator.len() - 1) * 2 > PARITY_LEN {
+        return None;
+    }
+    Some(locator)
+}
+
+// Chien search, returns the indices of the wrong bytes in the block
+fn error_positions(locator: &[u8]) -> Option<Vec<usize>> {
+    let reversed: Vec<u8> = locator.iter().rev().copied().collect();
+    let errors = locator.len() - 1;
+    let positions: Vec<usize> = (0..BLOCK_LEN)
+        .filter(|i| poly_eval(&reversed, alpha_pow(*i as i32)) == 0)
+        .map(|i| BLOCK_LEN - 1 - i)
+        .collect();
+    (positions.len() == errors).then_some(positions)
+}
+
+// Forney algorithm
+fn correct_errata(block: &mut [u8; BLOCK_LEN], syndromes: &[u8], positions: &[usize]) {
+    let coefficient_positions: Vec<i32> = positions
+        .iter()
+        .map(|p| (BLOCK_LEN - 1 - p) as i32)
+        .collect();
+    let locator = coefficient_positions.iter().fold(vec![1], |l, p| {
+        poly_mul(&l, &poly_add(&[1], &[alpha_pow(*p), 0]))
+    });
+
+    // Error evaluator, the remainder of syndromes * locator divided by x^(errors + 1)
+    let reversed_syndromes: Vec<u8> = syndromes.iter().rev().copied().collect();
+    let product = poly_mul(&reversed_syndromes, &locator);
+    let evaluator_len = locator.len();
+    let evaluator: Vec<u8> = product[product.len() - evaluator_len..].to_vec();
+
+    let x: Vec<u8> = coefficient_positions
+        .iter()
+        .map(|p| alpha_pow(-(255 - p)))
+        .collect();
+    for (i, xi) in x.iter().enumerate() {
+        let xi_inverse = div(1, *xi);
+        let locator_derivative = x
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .fold(1, |product, (_, xj)| mul(product, 1 ^ mul(xi_inverse, *xj)));
+        let y = mul(*xi, poly_eval(&evaluator, xi_inverse));
+        block[positions[i]] ^= div(y, locator_derivative);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block() -> [u8; BLOCK_LEN] {
+        let data: Vec<u8> = (0..DATA_LEN).map(|i| (i * 7 + 3) as u8).collect();
+        encode(&data)
+    }
+
+    // Flips bits in `errors` bytes spread over the whole block, parity included
+    fn corrupt(block: &mut [u8; BLOCK_LEN], errors: usize) {
+        for i in 0..errors {
+            block[i * 15] ^= (i as u8).wrapping_mul(29) | 1;
+        }
+    }
+
+    #[test]
+    fn clean_block_decodes() {
+        let mut received = block();
+        assert_eq!(decode(&mut received), Some(0));
+        assert_eq!(received, block());
+    }
+
+    #[test]
+    fn corrects_up_to_16_errors() {
+        let mut received = block();
+        corrupt(&mut received, PARITY_LEN / 2);
+        assert_eq!(decode(&mut received), Some(PARITY_LEN / 2));
+        assert_eq!(received, block());
+    }
+
+    #[test]
+    fn leaves_block_with_17_errors_alone() {
+        let mut received = block();
+        corrupt(&mut received, PARITY_LEN / 2 + 1);
+        let corrupted = received;
+        assert_eq!(decode(&mut received), None);
+        assert_eq!(received, corrupted);
+    }
+}
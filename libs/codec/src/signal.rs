@@ -0,0 +1,152 @@
+// The tone bursts image-to-sound puts around every image, a start signal in front and an end
+// signal after it. sound-to-image listens for the same frequencies, so both sides always agree
+// on what the signals look like. Each burst closes with a short sweep: the steady tones repeat
+// every few hundred samples, the sweep only lines up with itself at one offset, which is what
+// lets the receiver find the exact sample a burst ends on.
+use serde::{Deserialize, Deserializer, Serialize};
+use std::f32::consts::PI;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ToneBurst {
+    pub frequencies: Vec<f32>,
+    // Seconds
+    pub duration: f32,
+    // Seconds of raised cosine fade at either end, to avoid clicks
+    pub fade: f32,
+    // Peak amplitude of all tones together
+    pub level: f32,
+    pub sweep: Sweep,
+}
+
+// A linear chirp, in Hz and seconds
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sweep {
+    pub from: f32,
+    pub to: f32,
+    pub duration: f32,
+}
+
+impl ToneBurst {
+    pub fn start() -> Self {
+        ToneBurst {
+            frequencies: vec![200.0, 16000.0],
+            duration: 0.5,
+            fade: 0.01,
+            level: 0.8,
+            sweep: Sweep {
+                from: 500.0,
+                to: 12000.0,
+                duration: 0.05,
+            },
+        }
+    }
+
+    pub fn end() -> Self {
+        ToneBurst {
+            frequencies: vec![1000.0, 8000.0],
+            duration: 0.3,
+            fade: 0.01,
+            level: 0.8,
+            sweep: Sweep {
+                from: 12000.0,
+                to: 500.0,
+                duration: 0.05,
+            },
+        }
+    }
+
+    // Tones and sweep together
+    pub fn len(&self, sample_rate: u32) -> usize {
+        self.tones_len(sample_rate) + self.sweep_len(sample_rate)
+    }
+
+    pub fn sweep_len(&self, sample_rate: u32) -> usize {
+        (self.sweep.duration * sample_rate as f32).round() as usize
+    }
+
+    fn tones_len(&self, sample_rate: u32) -> usize {
+        (self.duration * sample_rate as f32).round() as usize
+    }
+
+    pub fn synthesize(&self, sample_rate: u32) -> Vec<f32> {
+        let fade_len = (self.fade * sample_rate as f32).round() as usize;
+
+        let tones_len = self.tones_len(sample_rate);
+        let amplitude = self.level / self.frequencies.len().max(1) as f32;
+        let tones = (0..tones_len).map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            let tones: f32 = self
+                .frequencies
+                .iter()
+                .map(|frequency| (2.0 * PI * frequency * t).sin())
+                .sum();
+            tones * amplitude * envelope(i, tones_len, fade_len)
+        });
+
+        let sweep_len = self.sweep_len(sample_rate);
+        let Sweep { from, to, duration } = self.sweep;
+        let sweep = (0..sweep_len).map(move |i| {
+            let t = i as f32 / sample_rate as f32;
+            let phase = 2.0 * PI * (from * t + (to - from) * t * t / (2.0 * duration));
+            phase.sin() * self.level * envelope(i, sweep_len, fade_len)
+        });
+
+        tones.chain(sweep).collect()
+    }
+}
+
+// What a `[start_signal]` or `[end_signal]` table may hold. Anything left out keeps its value
+// from the burst the table is laid over, so a table that only changes the frequencies still gets
+// the sweep image-to-sound writes for that burst
+#[derive(Deserialize)]
+struct ToneBurstTable {
+    frequencies: Option<Vec<f32>>,
+    duration: Option<f32>,
+    fade: Option<f32>,
+    level: Option<f32>,
+    #[serde(default)]
+    sweep: SweepTable,
+}
+
+#[derive(Default, Deserialize)]
+struct SweepTable {
+    from: Option<f32>,
+    to: Option<f32>,
+    duration: Option<f32>,
+}
+
+impl ToneBurstTable {
+    fn over(self, burst: ToneBurst) -> ToneBurst {
+        ToneBurst {
+            frequencies: self.frequencies.unwrap_or(burst.frequencies),
+            duration: self.duration.unwrap_or(burst.duration),
+            fade: self.fade.unwrap_or(burst.fade),
+            level: self.level.unwrap_or(burst.level),
+            sweep: Sweep {
+                from: self.sweep.from.unwrap_or(burst.sweep.from),
+                to: self.sweep.to.unwrap_or(burst.sweep.to),
+                duration: self.sweep.duration.unwrap_or(burst.sweep.duration),
+            },
+        }
+    }
+}
+
+impl ToneBurst {
+    // For `#[serde(deserialize_with)]`, a start signal with anything left out as in `start()`
+    pub fn deserialize_start<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(ToneBurstTable::deserialize(deserializer)?.over(ToneBurst::start()))
+    }
+
+    // An end signal with anything left out as in `end()`
+    pub fn deserialize_end<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(ToneBurstTable::deserialize(deserializer)?.over(ToneBurst::end()))
+    }
+}
+
+fn envelope(i: usize, len: usize, fade_len: usize) -> f32 {
+    let edge = i.min(len - 1 - i);
+    if edge >= fade_len {
+        return 1.0;
+    }
+    0.5 - 0.5 * (PI * edge as f32 / fade_len as f32).cos()
+}
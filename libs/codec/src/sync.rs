@@ -0,0 +1,181 @@
+// Optional markers in front of every row. Without them a pixel's position follows from the
+// sample count alone, so a single dropped or duplicated sample shears the rest of the image.
+// With them the decoder looks for each marker close to where it should be and lines the row up
+// with it, so a slip only ever affects the row it happened in.
+
+// Barker code, it only correlates strongly with itself when lined up exactly, so it stands out
+// from the image around it
+pub const MARKER: [f32; 13] = [
+    1.0, 1.0, 1.0, 1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, 1.0, -1.0, 1.0,
+];
+
+// How many frames either side of where a marker should be it is looked for
+pub const SEARCH_RADIUS: usize = 8;
+
+// A weaker match counts as a lost marker, the row is then taken from where it was expected
+const MIN_SCORE: f32 = 0.6;
+
+pub fn frames_per_row(width: u32) -> usize {
+    MARKER.len() + width as usize
+}
+
+// Puts a marker in front of every `width` frames of `samples`, on every channel
+pub fn insert_markers(samples: &[f32], width: u32, channels: usize) -> Vec<f32> {
+    let row_len = width as usize * channels;
+    let mut synced = Vec::with_capacity(samples.len() + samples.len() / row_len * MARKER.len());
+    for row in samples.chunks(row_len) {
+        for value in MARKER {
+            synced.extend(std::iter::repeat_n(value, channels));
+        }
+        synced.extend_from_slice(row);
+    }
+    synced
+}
+
+// Removes the markers again, a frame at a time so it can sit in the live capture
+pub struct RowAligner {
+    width: usize,
+    channels: usize,
+    // Interleaved frames not turned into a row yet
+    pending: Vec<f32>,
+    // Frame in `pending` where the next marker should begin
+    expected: usize,
+}
+
+impl RowAligner {
+    pub fn new(width: u32, channels: usize) -> Self {
+        RowAligner {
+            width: width as usize,
+            channels,
+            pending: Vec::new(),
+            expected: 0,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width as u32
+    }
+
+    // The next frame pushed is where the first marker should begin
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.expected = 0;
+    }
+
+    // Appends the frames of a row to `output` whenever one is complete
+    pub fn push(&mut self, frame: &[f32], output: &mut Vec<f32>) {
+        self.pending
+            .extend((0..self.channels).map(|channel| frame.get(channel).copied().unwrap_or(0.0)));
+        let frames = self.pending.len() / self.channels;
+        if frames < self.expected + SEARCH_RADIUS + MARKER.len() + self.width {
+            return;
+        }
+
+        let marker = self.find_marker(self.expected + SEARCH_RADIUS);
+        let row_start = (marker + MARKER.len()) * self.channels;
+        output.extend_from_slice(&self.pending[row_start..row_start + self.width * self.channels]);
+
+        // Keep enough in front of the next marker to look for it a little early
+        let next = marker + MARKER.len() + self.width;
+        let consumed = next.saturating_sub(SEARCH_RADIUS);
+        self.pending.drain(..consumed * self.channels);
+        self.expected = next - consumed;
+    }
+
+    // Appends what there is of the last row once no more frames are coming, `push` waits for
+    // frames past the end of a row before it takes it
+    pub fn flush(&mut self, output: &mut Vec<f32>) {
+        let frames = self.pending.len() / self.channels;
+        if frames > self.expected + MARKER.len() {
+            // Only look where the whole row would still fit, if it does anywhere
+            let latest = frames
+                .saturating_sub(MARKER.len() + self.width)
+                .min(self.expected + SEARCH_RADIUS);
+            let marker = self.find_marker(latest);
+            let row_start = (marker + MARKER.len()) * self.channels;
+            let row_end = (row_start + self.width * self.channels).min(self.pending.len());
+            output.extend_from_slice(&self.pending[row_start..row_end]);
+        }
+        self.reset();
+    }
+
+    // Returns the frame up to `latest` the best match of the marker begins on, or where it was
+    // expected if nothing matches well enough. Markers are on every channel, the first one is
+    // enough
+    fn find_marker(&self, latest: usize) -> usize {
+        let marker_energy: f32 = MARKER.iter().map(|value| value * value).sum();
+        let sample = |frame: usize| self.pending[frame * self.channels];
+        (self.expected.saturating_sub(SEARCH_RADIUS)..=latest)
+            .map(|start| {
+                let (dot, energy) =
+                    MARKER
+                        .iter()
+                        .enumerate()
+                        .fold((0.0, 0.0), |(dot, energy), (i, value)| {
+                            let s = sample(start + i);
+                            (dot + value * s, energy + s * s)
+                        });
+                let score = dot / (marker_energy * energy).sqrt().max(f32::MIN_POSITIVE);
+                (start, score)
+            })
+            .filter(|(_, score)| *score >= MIN_SCORE)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(self.expected, |(start, _)| start)
+    }
+}
+
+// Lines up every row of a whole recording, `samples` starting with the first marker
+pub fn align_rows(samples: &[f32], width: u32, channels: usize) -> Vec<f32> {
+    let mut aligner = RowAligner::new(width, channels);
+    let mut aligned = Vec::with_capacity(samples.len());
+    for frame in samples.chunks(channels) {
+        aligner.push(frame, &mut aligned);
+    }
+    aligner.flush(&mut aligned);
+    aligned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 37;
+    const HEIGHT: u32 = 11;
+    const CHANNELS: usize = 3;
+
+    fn image() -> Vec<f32> {
+        (0..WIDTH as usize * HEIGHT as usize * CHANNELS)
+            .map(|i| ((i * 37) % 101) as f32 / 101.0 - 0.5)
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let samples = image();
+        let synced = insert_markers(&samples, WIDTH, CHANNELS);
+        assert_eq!(align_rows(&synced, WIDTH, CHANNELS), samples);
+    }
+
+    #[test]
+    fn dropped_frame_only_damages_its_row() {
+        let samples = image();
+        let mut synced = insert_markers(&samples, WIDTH, CHANNELS);
+        let row_len = WIDTH as usize * CHANNELS;
+        let synced_row_len = frames_per_row(WIDTH) * CHANNELS;
+        // Drop a frame in the middle of the third row
+        let dropped = 2 * synced_row_len + (MARKER.len() + 10) * CHANNELS;
+        synced.drain(dropped..dropped + CHANNELS);
+
+        let aligned = align_rows(&synced, WIDTH, CHANNELS);
+        assert_eq!(aligned.len(), samples.len());
+        for (row, (aligned, samples)) in aligned
+            .chunks(row_len)
+            .zip(samples.chunks(row_len))
+            .enumerate()
+        {
+            if row != 2 {
+                assert_eq!(aligned, samples, "row {}", row);
+            }
+        }
+    }
+}
@@ -0,0 +1,256 @@
+// Tone mapping applied to pixel values before they are encoded. Whatever the options, the mapping
+// boils down to a curve from every input value to an output value, which is stored in the
+// metadata so the decoder can undo it.
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMap {
+    // Values are raised to 1 / gamma, so above 1.0 brightens the mid tones
+    pub gamma: f32,
+    // Scales the distance from mid grey
+    pub contrast: f32,
+    // Spreads the values out so every output value is used about equally often
+    pub equalise: bool,
+    // Diffuses the rounding error over neighbouring pixels instead of banding
+    pub dither: bool,
+}
+
+impl Default for ToneMap {
+    fn default() -> Self {
+        ToneMap {
+            gamma: 1.0,
+            contrast: 1.0,
+            equalise: false,
+            dither: false,
+        }
+    }
+}
+
+impl ToneMap {
+    pub fn is_identity(&self) -> bool {
+        self.gamma == 1.0 && self.contrast == 1.0 && !self.equalise
+    }
+
+    // Maps `values`, laid out `width` pixels of `channels` values per row, and quantises them to
+    // `levels` evenly spaced values. Returns the mapped values and the curve they went through
+    pub fn apply(
+        &self,
+        values: &[u8],
+        width: u32,
+        channels: usize,
+        levels: u32,
+    ) -> (Vec<u8>, ToneCurve) {
+        let curve = self.curve(values);
+        let mut mapped: Vec<f32> = values.iter().map(|value| curve[*value as usize]).collect();
+        let step = 255.0 / (levels - 1) as f32;
+        let quantise = |value: f32| ((value / step).round() * step).clamp(0.0, 255.0);
+
+        if self.dither {
+            // Floyd-Steinberg, every channel on its own
+            let row_len = width as usize * channels;
+            for i in 0..mapped.len() {
+                let old = mapped[i];
+                let new = quantise(old);
+                mapped[i] = new;
+                let error = old - new;
+                let x = i % row_len / channels;
+                let mut spread = |offset: usize, weight: f32| {
+                    if let Some(value) = mapped.get_mut(i + offset) {
+                        *value += error * weight;
+                    }
+                };
+                if x + 1 < width as usize {
+                    spread(channels, 7.0 / 16.0);
+                    spread(row_len + channels, 1.0 / 16.0);
+                }
+                if x > 0 {
+                    spread(row_len - channels, 3.0 / 16.0);
+                }
+                spread(row_len, 5.0 / 16.0);
+            }
+        }
+
+        let quantised = mapped
+            .into_iter()
+            .map(|value| quantise(value).round() as u8)
+            .collect();
+        let table = std::array::from_fn(|value| curve[value].round().clamp(0.0, 255.0) as u8);
+        (quantised, ToneCurve(table))
+    }
+
+    fn curve(&self, values: &[u8]) -> [f32; 256] {
+        let equalised = if self.equalise {
+            equalisation(values)
+        } else {
+            std::array::from_fn(|value| value as f32)
+        };
+        equalised.map(|value| {
+            let value = 255.0 * (value / 255.0).powf(1.0 / self.gamma);
+            ((value - 127.5) * self.contrast + 127.5).clamp(0.0, 255.0)
+        })
+    }
+}
+
+// Maps every value to where it falls in the cumulative histogram
+fn equalisation(values: &[u8]) -> [f32; 256] {
+    let mut histogram = [0usize; 256];
+    for value in values {
+        histogram[*value as usize] += 1;
+    }
+    let mut cumulative = [0usize; 256];
+    let mut total = 0;
+    for (value, count) in histogram.iter().enumerate() {
+        total += count;
+        cumulative[value] = total;
+    }
+    let lowest = cumulative
+        .iter()
+        .copied()
+        .find(|count| *count > 0)
+        .unwrap_or(0);
+    let range = total.saturating_sub(lowest).max(1) as f32;
+    cumulative.map(|count| count.saturating_sub(lowest) as f32 / range * 255.0)
+}
+
+// The output value for every input value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ToneCurve(pub [u8; 256]);
+
+impl ToneCurve {
+    // Maps output values back to the input values they most likely came from. The curve only
+    // ever rises, so every output value stands for a run of input values, or falls between two
+    // of them if nothing mapped to it exactly
+    pub fn inverse(&self) -> [u8; 256] {
+        let curve = &self.0;
+        std::array::from_fn(|output| {
+            let output = output as u8;
+            let below = curve.iter().rposition(|value| *value < output);
+            let above = curve.iter().position(|value| *value > output);
+            let first = below.map_or(0, |i| i + 1);
+            let last = above.map_or(255, |i| i.saturating_sub(1));
+            if first <= last {
+                return ((first + last) / 2) as u8;
+            }
+            // Nothing maps to `output`, interpolate between its neighbours
+            match (below, above) {
+                (Some(below), Some(above)) => {
+                    let (low, high) = (curve[below] as f32, curve[above] as f32);
+                    let t = (output as f32 - low) / (high - low);
+                    (below as f32 + t * (above - below) as f32).round() as u8
+                }
+                (Some(below), None) => below as u8,
+                (None, Some(above)) => above as u8,
+                (None, None) => output,
+            }
+        })
+    }
+}
+
+impl fmt::Display for ToneCurve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let values: Vec<String> = self.0.iter().map(|value| value.to_string()).collect();
+        write!(f, "{}", values.join(","))
+    }
+}
+
+impl FromStr for ToneCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<u8>())
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|e| format!("invalid tone curve: {}", e))?;
+        let table = values.try_into().map_err(|values: Vec<u8>| {
+            format!("tone curve has {} values, not 256", values.len())
+        })?;
+        Ok(ToneCurve(table))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_VALUES: [u8; 256] = {
+        let mut values = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            values[i] = i as u8;
+            i += 1;
+        }
+        values
+    };
+
+    fn curve(tone_map: ToneMap) -> ToneCurve {
+        tone_map.apply(&ALL_VALUES, 16, 1, 256).1
+    }
+
+    #[test]
+    fn curve_text_round_trip() {
+        let curve = curve(ToneMap {
+            gamma: 2.2,
+            contrast: 1.5,
+            ..ToneMap::default()
+        });
+        assert_eq!(curve.to_string().parse::<ToneCurve>(), Ok(curve));
+        assert!("1,2,3".parse::<ToneCurve>().is_err());
+    }
+
+    #[test]
+    fn identity_inverts_exactly() {
+        let curve = curve(ToneMap::default());
+        assert_eq!(curve.inverse(), ALL_VALUES);
+    }
+
+    #[test]
+    fn inverse_undoes_rising_curves() {
+        for gamma in [0.5, 1.0, 2.2] {
+            for contrast in [0.0, 0.5, 1.0, 2.0] {
+                let curve = curve(ToneMap {
+                    gamma,
+                    contrast,
+                    ..ToneMap::default()
+                });
+                let inverse = curve.inverse();
+                for value in 0..256 {
+                    // Values the curve merged can't be told apart, any of them will do
+                    let output = curve.0[value];
+                    assert_eq!(
+                        curve.0[inverse[output as usize] as usize], output,
+                        "gamma {} contrast {} value {}",
+                        gamma, contrast, value
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn equalise_uses_the_whole_range() {
+        let values: Vec<u8> = (0..1000).map(|i| 100 + (i % 21) as u8).collect();
+        let tone_map = ToneMap {
+            equalise: true,
+            ..ToneMap::default()
+        };
+        let (mapped, _) = tone_map.apply(&values, 100, 1, 256);
+        assert_eq!(mapped.iter().min(), Some(&0));
+        assert_eq!(mapped.iter().max(), Some(&255));
+    }
+
+    #[test]
+    fn dither_stays_on_the_levels() {
+        let values: Vec<u8> = (0..64 * 64).map(|i| (i % 64 * 4) as u8).collect();
+        let tone_map = ToneMap {
+            dither: true,
+            ..ToneMap::default()
+        };
+        let (mapped, _) = tone_map.apply(&values, 64, 1, 16);
+        assert!(mapped.iter().all(|value| value % 17 == 0));
+        // The rounding error is spread around rather than lost
+        let mean = |values: &[u8]| values.iter().map(|v| *v as f32).sum::<f32>() / 4096.0;
+        assert!((mean(&mapped) - mean(&values)).abs() < 2.0);
+    }
+}